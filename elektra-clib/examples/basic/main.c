@@ -28,14 +28,15 @@ int main (void) {
     printf("%p\n", keyValue (key));
     printf("%s\n", (char *) keyValue (key));
 
-    keyDel (key);
-    keyDel (key2);
+    printf("--------------\n");
 
-    // KeySet * ks = ksNew (1, KS_END);
-    // ksAppendKey(ks, key);
+    KeySet * ks = ksNew (1, KS_END);
+    ksAppendKey(ks, key);
+    ksAppendKey(ks, key2);
+    printf("%zd\n", ksGetSize (ks));
 
-    // Key * foundKey = ksLookupByName(ks, "test", 0);
-    // printf("%s\n", keyName (foundKey));
+    Key * foundKey = ksLookupByName(ks, "system:/asd/qwe/asd", 0);
+    printf("%s\n", keyName (foundKey));
 
-    // ksDel(ks);
+    ksDel(ks);
 }
\ No newline at end of file
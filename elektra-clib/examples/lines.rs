@@ -0,0 +1,85 @@
+//! The `lines` storage plugin of the interop tests written in Rust, exported
+//! as libelektra plugin: one `name<separator>value` line per key, with the
+//! separator from the configuration key `user:/separator`.
+//!
+//! Built as `liblines.so`, it is loaded by the mountpoints once installed as
+//! `libelektra-lines.so`.
+
+use std::fs;
+use std::io;
+use std::str::FromStr;
+
+use elektra::kdb::KdbError;
+use elektra::key::{Key, KeyName, KeySet};
+use elektra::plugin::{module_name, Plugin};
+
+struct Lines {
+    separator: String,
+}
+
+impl Plugin for Lines {
+    fn name(&self) -> &str {
+        "lines"
+    }
+
+    fn contract(&self) -> KeySet {
+        let mut contract = KeySet::default();
+        contract.append_key(Key::new(module_name(self.name())));
+
+        let mut name = module_name(self.name());
+        name.append_part(b"infos");
+        name.append_part(b"provides");
+
+        let mut provides = Key::new(name);
+        provides.set_string("storage").expect("a new key is not locked");
+        contract.append_key(provides);
+
+        contract
+    }
+
+    fn open(&mut self, config: &KeySet, _error_key: &mut Key) -> Result<(), KdbError> {
+        let separator = KeyName::from_str("user:/separator").expect("user:/separator is a valid key name");
+
+        if let Some(separator) = config.lookup(&separator) {
+            self.separator = separator.borrow().string().unwrap_or_default().to_string();
+        }
+
+        Ok(())
+    }
+
+    fn get(&mut self, returned: &mut KeySet, parent: &mut Key) -> Result<bool, KdbError> {
+        let path = parent.string().unwrap_or_default().to_string();
+
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(error) => return Err(KdbError::Resource(format!("{}: {}", path, error))),
+        };
+
+        for line in content.lines() {
+            let (name, value) = line.split_once(self.separator.as_str())
+                .ok_or_else(|| KdbError::ValidationSyntactic("a line has no separator".to_string()))?;
+
+            let mut key = Key::from_str(name)
+                .map_err(|_| KdbError::ValidationSyntactic(format!("{} is no valid key name", name)))?;
+            key.set_string(value).expect("a new key is not locked");
+            returned.append_key(key);
+        }
+
+        Ok(true)
+    }
+
+    fn set(&mut self, returned: &mut KeySet, parent: &mut Key) -> Result<bool, KdbError> {
+        let path = parent.string().unwrap_or_default().to_string();
+
+        let content: String = returned.values()
+            .map(|(name, key)| format!("{}{}{}\n", name, self.separator, key.borrow().string().unwrap_or_default()))
+            .collect();
+
+        fs::write(&path, content).map_err(|error| KdbError::Resource(format!("{}: {}", path, error)))?;
+
+        Ok(true)
+    }
+}
+
+elektra_rust::export_plugin!(|| Box::new(Lines { separator: "=".to_string() }));
@@ -0,0 +1,278 @@
+//! Exports plugins written in Rust as libelektra plugins, which stock
+//! libelektra hosts like the `kdb` tool can mount:
+//!
+//! ```ignore
+//! elektra_rust::export_plugin!(|| Box::new(Upper::default()));
+//! ```
+//!
+//! Built as `cdylib` and installed as `libelektra-<name>.so`, the plugin
+//! uses the keys of the host only through the C API the host provides, see
+//! [`host`], so the same plugin works with libelektra and with this library.
+
+use std::ffi::CString;
+use std::ptr;
+use std::sync::OnceLock;
+use libc::{c_int, c_void};
+
+use elektra::kdb::KdbError;
+use elektra::key::{Key, KeySet};
+use elektra::plugin::{PluginFactory, PluginInstance};
+
+use crate::catch_panic;
+use crate::structs::{
+    ELEKTRA_PLUGIN_END, ELEKTRA_PLUGIN_OPEN, ELEKTRA_PLUGIN_CLOSE, ELEKTRA_PLUGIN_GET, ELEKTRA_PLUGIN_SET,
+    ELEKTRA_PLUGIN_ERROR, ELEKTRA_PLUGIN_COMMIT,
+};
+
+mod convert;
+pub mod host;
+
+pub use self::host::HostPlugin;
+use self::host::{Host, HostKey, HostKeySet};
+
+/// Exports the plugins made by `factory` under `elektraPluginSymbol`, see
+/// the [module documentation](crate::export).
+#[macro_export]
+macro_rules! export_plugin {
+    ($factory:expr) => {
+        #[no_mangle]
+        #[allow(non_snake_case)]
+        pub extern "C" fn elektraPluginSymbol() -> *mut $crate::export::HostPlugin {
+            $crate::export::plugin($factory)
+        }
+    };
+}
+
+/// The name of the plugin. libelektra keeps the pointer to it instead of
+/// copying it, so it has to live as long as the library.
+static NAME: OnceLock<CString> = OnceLock::new();
+
+/// The Rust side of an exported plugin, stored as data of the plugin of the
+/// host. The plugin is created by `open`, with the configuration of the
+/// plugin of the host.
+struct Exported {
+    factory: PluginFactory,
+    instance: Option<PluginInstance>,
+}
+
+/// Creates the plugin of the host for the plugins made by `factory`, see
+/// [`export_plugin`](crate::export_plugin).
+pub fn plugin(factory: PluginFactory) -> *mut HostPlugin {
+    catch_panic(ptr::null_mut(), || {
+        let host = match host::host() {
+            Some(x) => x,
+            None => return ptr::null_mut(),
+        };
+
+        let name = match NAME.get() {
+            Some(x) => x,
+            None => match CString::new(factory().name()) {
+                Ok(name) => NAME.get_or_init(|| name),
+                Err(_) => return ptr::null_mut(),
+            },
+        };
+
+        let handle = unsafe {
+            (host.elektraPluginExport)(
+                name.as_ptr(),
+                ELEKTRA_PLUGIN_OPEN, open as host::kdbOpenPtr,
+                ELEKTRA_PLUGIN_CLOSE, close as host::kdbOpenPtr,
+                ELEKTRA_PLUGIN_GET, get as host::kdbGetPtr,
+                ELEKTRA_PLUGIN_SET, set as host::kdbGetPtr,
+                ELEKTRA_PLUGIN_ERROR, error as host::kdbGetPtr,
+                ELEKTRA_PLUGIN_COMMIT, commit as host::kdbGetPtr,
+                ELEKTRA_PLUGIN_END,
+            )
+        };
+
+        if !handle.is_null() {
+            let exported = Box::new(Exported { factory, instance: None });
+            unsafe { (host.elektraPluginSetData)(handle, Box::into_raw(exported) as *mut c_void) };
+        }
+
+        handle
+    })
+}
+
+fn exported<'a>(host: &Host, handle: *mut HostPlugin) -> Option<&'a mut Exported> {
+    if handle.is_null() {
+        return None;
+    }
+
+    unsafe { ((host.elektraPluginGetData)(handle) as *mut Exported).as_mut() }
+}
+
+fn status(result: Result<bool, KdbError>) -> c_int {
+    match result {
+        Ok(true) => 1,
+        Ok(false) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// Calls `f` with a copy of the key `key` of the host, and writes the
+/// changes back.
+fn with_host_key<T, F: FnOnce(&mut Key) -> T>(host: &Host, key: *mut HostKey, f: F) -> T {
+    let before = match (!key.is_null()).then(|| convert::key_from_host(host, key)).flatten() {
+        Some(x) => x,
+        None => return f(&mut Key::new("/".parse().expect("/ is a valid key name"))),
+    };
+
+    let mut after = before.clone();
+    let result = f(&mut after);
+
+    convert::write_key_back(host, key, &before, &after);
+
+    result
+}
+
+unsafe extern "C" fn open(handle: *mut HostPlugin, error_key: *mut HostKey) -> c_int {
+    catch_panic(-1, || {
+        let (host, exported) = match host::host().and_then(|host| Some((host, exported(host, handle)?))) {
+            Some(x) => x,
+            None => return -1,
+        };
+
+        let config = convert::key_set_from_host(host, unsafe { (host.elektraPluginGetConfig)(handle) });
+        let instance = with_host_key(host, error_key, |key| PluginInstance::open((exported.factory)(), config, key));
+
+        status(instance.map(|instance| {
+            exported.instance = Some(instance);
+            true
+        }))
+    })
+}
+
+unsafe extern "C" fn close(handle: *mut HostPlugin, error_key: *mut HostKey) -> c_int {
+    catch_panic(-1, || {
+        let host = match host::host() {
+            Some(x) if exported(x, handle).is_some() => x,
+            _ => return -1,
+        };
+
+        let exported = unsafe { Box::from_raw((host.elektraPluginGetData)(handle) as *mut Exported) };
+        unsafe { (host.elektraPluginSetData)(handle, ptr::null_mut()) };
+
+        match exported.instance {
+            Some(instance) => status(with_host_key(host, error_key, |key| instance.close(key)).map(|_| true)),
+            None => 1,
+        }
+    })
+}
+
+/// Calls `f` with copies of `returned` and `parent_key`, and writes the
+/// changes back. The keys in `returned` are only written back if the plugin
+/// succeeds and reports a change.
+fn call(
+    handle: *mut HostPlugin,
+    returned: *mut HostKeySet,
+    parent_key: *mut HostKey,
+    f: fn(&mut PluginInstance, &mut KeySet, &mut Key) -> Result<bool, KdbError>,
+) -> c_int {
+    catch_panic(-1, || {
+        let (host, exported) = match host::host().and_then(|host| Some((host, exported(host, handle)?))) {
+            Some(x) if !returned.is_null() && !parent_key.is_null() => x,
+            _ => return -1,
+        };
+
+        let instance = match exported.instance.as_mut() {
+            Some(x) => x,
+            None => {
+                let error = KdbError::Interface("the plugin has to be opened first".to_string());
+                with_host_key(host, parent_key, |key| error.set_on(key));
+                return -1;
+            }
+        };
+
+        let before = convert::key_set_from_host(host, returned);
+        let mut ks = convert::key_set_from_host(host, returned);
+
+        let result = status(with_host_key(host, parent_key, |key| f(instance, &mut ks, key)));
+
+        if result > 0 {
+            convert::write_key_set_back(host, returned, &before, &ks);
+        }
+
+        result
+    })
+}
+
+unsafe extern "C" fn get(handle: *mut HostPlugin, returned: *mut HostKeySet, parent_key: *mut HostKey) -> c_int {
+    call(handle, returned, parent_key, PluginInstance::get)
+}
+
+unsafe extern "C" fn set(handle: *mut HostPlugin, returned: *mut HostKeySet, parent_key: *mut HostKey) -> c_int {
+    call(handle, returned, parent_key, PluginInstance::set)
+}
+
+unsafe extern "C" fn error(handle: *mut HostPlugin, returned: *mut HostKeySet, parent_key: *mut HostKey) -> c_int {
+    call(handle, returned, parent_key, PluginInstance::error)
+}
+
+unsafe extern "C" fn commit(handle: *mut HostPlugin, returned: *mut HostKeySet, parent_key: *mut HostKey) -> c_int {
+    call(handle, returned, parent_key, PluginInstance::commit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+    use std::str::FromStr;
+    use elektra::plugin::Plugin;
+
+    use crate::structs::{CKey, CKeySet, CPlugin};
+    use crate::{keyDel, keyString, ksAtCursor, ksDel, ksGetSize};
+
+    struct Constant;
+
+    impl Plugin for Constant {
+        fn name(&self) -> &str {
+            "constant"
+        }
+
+        fn get(&mut self, returned: &mut KeySet, _parent: &mut Key) -> Result<bool, KdbError> {
+            let mut key = Key::from_str("user:/tests/constant").unwrap();
+            key.set_string("1").unwrap();
+            returned.append_key(key);
+
+            Ok(true)
+        }
+    }
+
+    #[test]
+    fn test_export_plugin() {
+        let handle = plugin(|| Box::new(Constant));
+        assert!(!handle.is_null());
+
+        let c_plugin = unsafe { &*(handle as *mut CPlugin) };
+        assert_eq!(c_plugin.name.to_str(), Ok("constant"));
+        assert!(!c_plugin.data.is_null());
+
+        let open = c_plugin.kdbOpen.unwrap();
+        let get = c_plugin.kdbGet.unwrap();
+        assert!(c_plugin.kdbSet.is_some());
+        assert!(c_plugin.kdbError.is_some());
+        assert!(c_plugin.kdbCommit.is_some());
+        let close = c_plugin.kdbClose.unwrap();
+
+        let parent = Box::into_raw(Box::new(CKey::from(Key::from_str("user:/tests").unwrap())));
+        let returned = Box::into_raw(Box::new(CKeySet::from(KeySet::default())));
+        let plugin = handle as *mut CPlugin;
+
+        unsafe {
+            assert_eq!(get(plugin, returned, parent), -1, "get before open should fail");
+
+            assert_eq!(open(plugin, parent), 1);
+            assert_eq!(get(plugin, returned, parent), 1);
+            assert_eq!(ksGetSize(returned), 1);
+            assert_eq!(CStr::from_ptr(keyString(ksAtCursor(returned, 0))).to_str(), Ok("1"));
+
+            assert_eq!(close(plugin, parent), 1);
+            assert!((*plugin).data.is_null());
+
+            ksDel(returned);
+            keyDel(parent);
+            drop(Box::from_raw(plugin));
+        }
+    }
+}
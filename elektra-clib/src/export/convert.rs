@@ -0,0 +1,300 @@
+//! Copies keys between the host and Rust. Keys of the host are read and
+//! written only through the functions of the host, never through their
+//! layout.
+
+use std::ffi::{CStr, CString};
+use std::{ptr, slice};
+use libc::c_char;
+
+use elektra::key::{Key, KeyName, KeySet, KeyValue};
+
+use super::host::{self, Host, HostKey, HostKeySet};
+
+/// The keys of a key set of the host, in order.
+fn host_keys(host: &Host, ks: *const HostKeySet) -> impl Iterator<Item = *mut HostKey> + '_ {
+    let size = if ks.is_null() { 0 } else { unsafe { (host.ksGetSize)(ks) }.max(0) };
+
+    (0..size).map(move |position| unsafe { (host.ksAtCursor)(ks, position) })
+}
+
+/// Copies a key of the host with its value and metadata. Returns `None`
+/// if the host has a name that is not valid here.
+pub fn key_from_host(host: &Host, key: *mut HostKey) -> Option<Key> {
+    let name = unsafe { CStr::from_ptr((host.keyName)(key)) };
+    let mut rust_key = Key::new(KeyName::from_bytes(name.to_bytes()).ok()?);
+
+    rust_key.set_value(value_from_host(host, key)).ok()?;
+
+    for meta in host_keys(host, unsafe { (host.keyMeta)(key) }) {
+        let name = unsafe { CStr::from_ptr((host.keyName)(meta)) };
+        let value = unsafe { CStr::from_ptr((host.keyString)(meta)) };
+
+        rust_key.set_meta(name.to_bytes(), value.to_bytes()).ok()?;
+    }
+
+    Some(rust_key)
+}
+
+fn value_from_host(host: &Host, key: *mut HostKey) -> KeyValue {
+    if unsafe { (host.keyIsBinary)(key) } != 1 {
+        return KeyValue::String(unsafe { CStr::from_ptr((host.keyString)(key)) }.to_bytes().to_vec());
+    }
+
+    let value = unsafe { (host.keyValue)(key) };
+    let size = unsafe { (host.keyGetValueSize)(key) };
+
+    if value.is_null() || size <= 0 {
+        return KeyValue::Binary(Vec::new());
+    }
+
+    KeyValue::Binary(unsafe { slice::from_raw_parts(value as *const u8, size as usize) }.to_vec())
+}
+
+/// Copies a key set of the host, skipping keys that cannot be copied.
+pub fn key_set_from_host(host: &Host, ks: *const HostKeySet) -> KeySet {
+    host_keys(host, ks).filter_map(|key| key_from_host(host, key)).collect()
+}
+
+/// Whether `a` and `b` have the same value and metadata.
+fn same_content(a: &Key, b: &Key) -> bool {
+    a.value() == b.value()
+        && a.meta_keys().size() == b.meta_keys().size()
+        && a.meta_keys().values().zip(b.meta_keys().values()).all(|((a_name, a_meta), (b_name, b_meta))| {
+            a_name == b_name && a_meta.borrow().value() == b_meta.borrow().value()
+        })
+}
+
+/// Writes the changes from `before` to `after` back to the key of the host
+/// they were copied from. The name is left alone.
+pub fn write_key_back(host: &Host, key: *mut HostKey, before: &Key, after: &Key) {
+    if before.value() != after.value() {
+        write_value(host, key, after);
+    }
+
+    for (name, meta) in after.meta_keys().values() {
+        let unchanged = before.meta_keys().get(name)
+            .is_some_and(|before| before.value() == meta.borrow().value());
+
+        if !unchanged {
+            set_host_meta(host, key, name, meta.borrow().value());
+        }
+    }
+
+    for (name, _) in before.meta_keys().values() {
+        if !after.meta_keys().contains(name) {
+            set_host_meta(host, key, name, None);
+        }
+    }
+}
+
+fn write_value(host: &Host, key: *mut HostKey, rust_key: &Key) {
+    match rust_key.value() {
+        Some(KeyValue::Binary(value)) => {
+            let data = if value.is_empty() { ptr::null() } else { value.as_ptr() };
+            unsafe { (host.keySetBinary)(key, data as *const _, value.len()) };
+        }
+        Some(KeyValue::String(value)) => {
+            if let Ok(value) = CString::new(value.as_slice()) {
+                unsafe { (host.keySetString)(key, value.as_ptr()) };
+            }
+        }
+        None => {
+            unsafe { (host.keySetString)(key, b"\0".as_ptr() as *const c_char) };
+        }
+    }
+}
+
+/// Sets or, without `value`, removes a meta key on a key of the host.
+fn set_host_meta(host: &Host, key: *mut HostKey, name: &KeyName, value: Option<&KeyValue>) {
+    // libelektra takes meta names without the namespace
+    let name = name.escaped();
+    let name = match CString::new(name.strip_prefix(b"meta:/").unwrap_or(&name)) {
+        Ok(x) => x,
+        Err(_) => return,
+    };
+
+    let value = match value {
+        Some(KeyValue::String(value)) | Some(KeyValue::Binary(value)) => CString::new(value.as_slice()).ok(),
+        None => None,
+    };
+
+    unsafe { (host.keySetMeta)(key, name.as_ptr(), value.as_ref().map_or(ptr::null(), |value| value.as_ptr())) };
+}
+
+/// Creates a key of the host as a copy of `rust_key`.
+fn key_to_host(host: &Host, rust_key: &Key) -> Option<*mut HostKey> {
+    let name = CString::new(rust_key.key_name().escaped()).ok()?;
+    let key = unsafe { (host.keyNew)(name.as_ptr(), host::KEY_END) };
+
+    if key.is_null() {
+        return None;
+    }
+
+    write_value(host, key, rust_key);
+
+    for (name, meta) in rust_key.meta_keys().values() {
+        set_host_meta(host, key, name, meta.borrow().value());
+    }
+
+    Some(key)
+}
+
+/// Makes the key set `ks` of the host hold the keys of `after`, where
+/// `before` is what was copied from `ks`. Keys whose content did not change
+/// are kept, so that the host sees them unchanged, and nothing is written
+/// back if no key changed.
+pub fn write_key_set_back(host: &Host, ks: *mut HostKeySet, before: &KeySet, after: &KeySet) {
+    let changed = before.size() != after.size()
+        || after.values().any(|(name, key)| {
+            before.get(name).is_none_or(|before| !same_content(&before, &key.borrow()))
+        });
+
+    if !changed {
+        return;
+    }
+
+    let result = unsafe { (host.ksNew)(after.size(), host::KS_END) };
+
+    for (name, key) in after.values() {
+        let key = key.borrow();
+
+        let kept = match before.get(name) {
+            Some(before) if same_content(&before, &key) => host_key(host, ks, name),
+            _ => None,
+        };
+
+        if let Some(key) = kept.or_else(|| key_to_host(host, &key)) {
+            unsafe { (host.ksAppendKey)(result, key) };
+        }
+    }
+
+    unsafe {
+        (host.ksCopy)(ks, result);
+        (host.ksDel)(result);
+    }
+}
+
+/// Looks up the key named exactly `name` in a key set of the host.
+fn host_key(host: &Host, ks: *mut HostKeySet, name: &KeyName) -> Option<*mut HostKey> {
+    let name = CString::new(name.escaped()).ok()?;
+    let key = unsafe { (host.ksLookupByName)(ks, name.as_ptr(), host::KDB_O_NOCASCADING) };
+
+    if key.is_null() { None } else { Some(key) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    use crate::structs::KeyNewFlags;
+
+    fn host() -> &'static Host {
+        host::host().unwrap()
+    }
+
+    fn new_key(name: &[u8], value: &[u8]) -> *mut HostKey {
+        let name = CString::new(name).unwrap();
+        let value = CString::new(value).unwrap();
+
+        unsafe { (host().keyNew)(name.as_ptr(), KeyNewFlags::KEY_VALUE.bits(), value.as_ptr(), host::KEY_END) }
+    }
+
+    fn string(key: *mut HostKey) -> String {
+        unsafe { CStr::from_ptr((host().keyString)(key)) }.to_str().unwrap().to_string()
+    }
+
+    fn meta(key: *mut HostKey, name: &str) -> Option<String> {
+        let name = CString::new(format!("meta:/{}", name)).unwrap();
+        let meta = unsafe { (host().ksLookupByName)((host().keyMeta)(key), name.as_ptr(), 0) };
+
+        (!meta.is_null()).then(|| string(meta))
+    }
+
+    #[test]
+    fn test_key_from_host() {
+        let key = new_key(b"user:/a", b"1");
+        unsafe { (host().keySetMeta)(key, b"type\0".as_ptr() as *const c_char, b"long\0".as_ptr() as *const c_char) };
+
+        let rust_key = key_from_host(host(), key).unwrap();
+        assert_eq!(rust_key.name(), "user:/a");
+        assert_eq!(rust_key.string(), Some("1"));
+        assert_eq!(rust_key.meta("type").unwrap().string(), Some("long"));
+
+        unsafe { (host().keySetBinary)(key, [0u8, 255].as_ptr() as *const _, 2) };
+        assert_eq!(key_from_host(host(), key).unwrap().binary(), Some(&[0, 255][..]));
+
+        unsafe { (host().keyDel)(key) };
+    }
+
+    #[test]
+    fn test_key_to_host() {
+        let mut rust_key = Key::from_str("user:/a/b").unwrap();
+        rust_key.set_string("value").unwrap();
+        rust_key.set_meta("type", "string").unwrap();
+
+        let key = key_to_host(host(), &rust_key).unwrap();
+        assert_eq!(unsafe { CStr::from_ptr((host().keyName)(key)) }.to_str(), Ok("user:/a/b"));
+        assert_eq!(string(key), "value");
+        assert_eq!(meta(key, "type").as_deref(), Some("string"));
+        assert!(same_content(&key_from_host(host(), key).unwrap(), &rust_key));
+
+        unsafe { (host().keyDel)(key) };
+    }
+
+    #[test]
+    fn test_write_key_back() {
+        let key = new_key(b"user:/a", b"1");
+        unsafe {
+            (host().keySetMeta)(key, b"kept\0".as_ptr() as *const c_char, b"1\0".as_ptr() as *const c_char);
+            (host().keySetMeta)(key, b"removed\0".as_ptr() as *const c_char, b"1\0".as_ptr() as *const c_char);
+        }
+
+        let before = key_from_host(host(), key).unwrap();
+        let mut after = key_from_host(host(), key).unwrap();
+        after.set_string("2").unwrap();
+        after.remove_meta("removed").unwrap();
+        after.set_meta("added", "1").unwrap();
+
+        write_key_back(host(), key, &before, &after);
+
+        assert_eq!(string(key), "2");
+        assert_eq!(meta(key, "kept").as_deref(), Some("1"));
+        assert_eq!(meta(key, "removed"), None);
+        assert_eq!(meta(key, "added").as_deref(), Some("1"));
+
+        unsafe { (host().keyDel)(key) };
+    }
+
+    #[test]
+    fn test_write_key_set_back() {
+        let kept = new_key(b"user:/a", b"1");
+        let changed = new_key(b"user:/b", b"1");
+        let ks = unsafe { (host().ksNew)(2, kept, changed, new_key(b"user:/c", b"1"), host::KS_END) };
+
+        let lookup = |name: &[u8]| {
+            let name = CString::new(name).unwrap();
+            unsafe { (host().ksLookupByName)(ks, name.as_ptr(), 0) }
+        };
+
+        // nothing changed, so the keys are left alone
+        let before = key_set_from_host(host(), ks);
+        write_key_set_back(host(), ks, &before, &key_set_from_host(host(), ks));
+        assert_eq!(lookup(b"user:/b"), changed);
+
+        let mut after = key_set_from_host(host(), ks);
+        after.lookup(&KeyName::from_str("user:/b").unwrap()).unwrap().borrow_mut().set_string("2").unwrap();
+        after.cut(&KeyName::from_str("user:/c").unwrap());
+        after.append_key(Key::from_str("user:/d").unwrap());
+
+        write_key_set_back(host(), ks, &before, &after);
+
+        assert_eq!(unsafe { (host().ksGetSize)(ks) }, 3);
+        assert_eq!(lookup(b"user:/a"), kept);
+        assert_eq!(string(lookup(b"user:/b")), "2");
+        assert!(lookup(b"user:/c").is_null());
+        assert!(!lookup(b"user:/d").is_null());
+
+        unsafe { (host().ksDel)(ks) };
+    }
+}
@@ -0,0 +1,105 @@
+//! The functions of the C API the host provides to exported plugins.
+//!
+//! An exported plugin links its own copy of this library, but the keys it
+//! gets belong to the host, which may be libelektra. So the plugin uses them
+//! only through the functions of the host, looked up by name when the plugin
+//! is created.
+
+use std::mem;
+use std::sync::OnceLock;
+use libc::{c_char, c_int, c_void, size_t, ssize_t};
+
+/// A key of the host, only used through the functions of [`Host`].
+#[repr(C)]
+pub struct HostKey {
+    _private: [u8; 0],
+}
+
+/// A key set of the host, only used through the functions of [`Host`].
+#[repr(C)]
+pub struct HostKeySet {
+    _private: [u8; 0],
+}
+
+/// A plugin of the host, only used through the functions of [`Host`].
+#[repr(C)]
+pub struct HostPlugin {
+    _private: [u8; 0],
+}
+
+pub const KEY_END: c_int = 0;
+pub const KS_END: *mut HostKey = std::ptr::null_mut();
+pub const KDB_O_NOCASCADING: c_int = 1 << 17;
+
+pub type kdbOpenPtr = unsafe extern "C" fn(*mut HostPlugin, *mut HostKey) -> c_int;
+pub type kdbGetPtr = unsafe extern "C" fn(*mut HostPlugin, *mut HostKeySet, *mut HostKey) -> c_int;
+
+macro_rules! host_functions {
+    ($($name:ident: $type:ty,)*) => {
+        pub struct Host {
+            $(pub $name: $type,)*
+        }
+
+        impl Host {
+            /// Looks the functions up in the libraries the host loaded. The
+            /// plugin is loaded after them, so its own copies are not found.
+            #[cfg(not(test))]
+            fn load() -> Option<Host> {
+                Some(Host {
+                    $($name: unsafe { mem::transmute::<*mut c_void, $type>(symbol(stringify!($name))?) },)*
+                })
+            }
+
+            /// The unit tests are their own host, with the functions of this
+            /// library.
+            #[cfg(test)]
+            fn load() -> Option<Host> {
+                Some(Host {
+                    $($name: unsafe { mem::transmute::<*const (), $type>(crate::$name as *const ()) },)*
+                })
+            }
+        }
+    };
+}
+
+host_functions! {
+    keyNew: unsafe extern "C" fn(*const c_char, ...) -> *mut HostKey,
+    keyName: unsafe extern "C" fn(*const HostKey) -> *const c_char,
+    keyValue: unsafe extern "C" fn(*const HostKey) -> *const c_void,
+    keyGetValueSize: unsafe extern "C" fn(*const HostKey) -> ssize_t,
+    keyIsBinary: unsafe extern "C" fn(*const HostKey) -> c_int,
+    keyString: unsafe extern "C" fn(*const HostKey) -> *const c_char,
+    keySetString: unsafe extern "C" fn(*mut HostKey, *const c_char) -> ssize_t,
+    keySetBinary: unsafe extern "C" fn(*mut HostKey, *const c_void, size_t) -> ssize_t,
+    keySetMeta: unsafe extern "C" fn(*mut HostKey, *const c_char, *const c_char) -> ssize_t,
+    keyMeta: unsafe extern "C" fn(*mut HostKey) -> *mut HostKeySet,
+    keyDel: unsafe extern "C" fn(*mut HostKey) -> c_int,
+
+    ksNew: unsafe extern "C" fn(size_t, ...) -> *mut HostKeySet,
+    ksCopy: unsafe extern "C" fn(*mut HostKeySet, *const HostKeySet) -> c_int,
+    ksDel: unsafe extern "C" fn(*mut HostKeySet) -> c_int,
+    ksGetSize: unsafe extern "C" fn(*const HostKeySet) -> ssize_t,
+    ksAppendKey: unsafe extern "C" fn(*mut HostKeySet, *mut HostKey) -> ssize_t,
+    ksAtCursor: unsafe extern "C" fn(*const HostKeySet, ssize_t) -> *mut HostKey,
+    ksLookupByName: unsafe extern "C" fn(*mut HostKeySet, *const c_char, c_int) -> *mut HostKey,
+
+    elektraPluginExport: unsafe extern "C" fn(*const c_char, ...) -> *mut HostPlugin,
+    elektraPluginGetConfig: unsafe extern "C" fn(*mut HostPlugin) -> *mut HostKeySet,
+    elektraPluginSetData: unsafe extern "C" fn(*mut HostPlugin, *mut c_void),
+    elektraPluginGetData: unsafe extern "C" fn(*mut HostPlugin) -> *mut c_void,
+}
+
+/// The functions of the host, or `None` if it lacks one of them.
+pub fn host() -> Option<&'static Host> {
+    static HOST: OnceLock<Option<Host>> = OnceLock::new();
+
+    HOST.get_or_init(Host::load).as_ref()
+}
+
+#[cfg(not(test))]
+fn symbol(name: &str) -> Option<*mut c_void> {
+    let name = std::ffi::CString::new(name).ok()?;
+    let symbol = unsafe { libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr()) };
+
+    if symbol.is_null() { None } else { Some(symbol) }
+}
@@ -1,454 +1,1657 @@
+//! The C API of libelektra on top of the `elektra` crate.
+//!
+//! Keys and key sets passed to the exports are valid if they were created
+//! by this library and are not deleted yet.
+
 #![feature(c_variadic)]
 
 #![allow(non_snake_case)]
 #![allow(non_camel_case_types)]
 #![allow(unused_variables)]
 
-use std::cmp::Ordering;
-use std::ffi::{CStr, CString, VaList, VaListImpl};
+use std::ffi::{CStr, VaList};
 use std::{ptr, slice};
-use std::str::FromStr;
-use std::convert::TryFrom;
+use std::panic::{self, AssertUnwindSafe};
 use libc::{ssize_t, size_t, c_char, c_int, c_void};
 
 mod structs;
+mod loader;
+pub mod export;
 
 use crate::structs::{
-    CKey, CKeySet,
-    KeyNewFlags, elektraNamespace, elektraCopyFlags, elektraLockFlags, elektraLookupFlags,
+    CKey, CKeySet, CPlugin, META_KEY_LOCKS,
+    kdbOpenPtr, kdbClosePtr, kdbGetPtr, kdbSetPtr, kdbErrorPtr, kdbCommitPtr,
+    KeyNewFlags, elektraNamespace, elektraCopyFlags, elektraLockFlags, elektraLookupFlags, plugin_t,
+    ELEKTRA_PLUGIN_END, ELEKTRA_PLUGIN_OPEN, ELEKTRA_PLUGIN_CLOSE, ELEKTRA_PLUGIN_GET, ELEKTRA_PLUGIN_SET,
+    ELEKTRA_PLUGIN_ERROR, ELEKTRA_PLUGIN_COMMIT,
 };
 
 use crate::elektraNamespace::KEY_NS_NONE;
 
-use elektra_rust::key::{Key, KeyBuilder, KeyName, KeyNamespace, KeySet};
+use elektra::kdb::{Kdb, KdbError};
+use elektra::plugin;
+use elektra::key::{Key, KeyBuilder, KeyCopyFlags, KeyLockFlags, KeyLookup, KeyLookupFlags, KeyName, KeyNamespace, KeySet, KeyValue};
+
+/// Runs the body of an export and returns `error` if it panics, as
+/// unwinding into the C caller would abort the process.
+fn catch_panic<T, F: FnOnce() -> T>(error: T, f: F) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(error)
+}
 
+/// # Safety
+///
+/// `keyname` has to be NULL or a NUL-terminated string, followed by the
+/// arguments `keyNew` documents, ending with `KEY_END`.
 #[no_mangle]
 pub unsafe extern "C" fn keyNew(keyname: *const c_char, args: ...) -> *const CKey {
-    let mut va_list: VaListImpl;
-    va_list = args.clone();
-    keyVNew(keyname, va_list.as_va_list())
+    keyVNew(keyname, args)
 }
 
+/// # Safety
+///
+/// `keyname` has to be NULL or a NUL-terminated string and `ap` has to hold
+/// the arguments `keyNew` documents, ending with `KEY_END`.
 #[no_mangle]
-pub extern "C" fn keyVNew(keyname: *const c_char, mut ap: VaList) -> *const CKey {
-    if keyname.is_null() {
-        return ptr::null_mut();
-    }
+pub unsafe extern "C" fn keyVNew(keyname: *const c_char, mut ap: VaList) -> *const CKey {
+    catch_panic(ptr::null(), || {
+        if keyname.is_null() {
+            return ptr::null_mut();
+        }
 
-    let cstr = unsafe { CStr::from_ptr(keyname) };
-    let keyNameStr = cstr.to_str()
-        .expect("key name cannot be cast to string");
+        let key_name = match KeyName::from_bytes(unsafe { CStr::from_ptr(keyname) }.to_bytes()) {
+            Ok(x) => x,
+            Err(_) => return ptr::null_mut(),
+        };
 
-    if let Ok(builder) = KeyBuilder::from_str(keyNameStr) {
-        let keyResult = builder.build();
+        let mut builder = KeyBuilder::new(key_name);
+        let mut binary = false;
+        let mut size = None;
+
+        loop {
+            let argument = unsafe { ap.next_arg::<c_int>() };
+
+            if argument == 0 {
+                break;
+            }
+
+            match KeyNewFlags::from_bits(argument) {
+                Some(KeyNewFlags::KEY_SIZE) => {
+                    size = Some(unsafe { ap.next_arg::<size_t>() });
+                }
+                Some(KeyNewFlags::KEY_BINARY) => {
+                    binary = true;
+                    builder = builder.binary();
+                }
+                Some(KeyNewFlags::KEY_VALUE) => {
+                    let value = unsafe { ap.next_arg::<*const c_void>() };
+                    builder = builder.value(new_value(value, binary, size));
+                }
+                Some(KeyNewFlags::KEY_META) => {
+                    let name = unsafe { ap.next_arg::<*const c_char>() };
+                    let value = unsafe { ap.next_arg::<*const c_char>() };
 
-        if let Ok(key) = keyResult {
-            loop {
-                let flag_argument = unsafe { ap.arg::<c_int>() };
+                    if name.is_null() || value.is_null() {
+                        return ptr::null_mut();
+                    }
 
-                let flags = KeyNewFlags::from_bits(flag_argument)
-                    .expect("Cannot create Flags from va_list args");
+                    let name = unsafe { CStr::from_ptr(name) }.to_bytes();
+                    let value = unsafe { CStr::from_ptr(value) }.to_bytes();
 
-                if flags.contains(KeyNewFlags::KEY_NAME) {
-                    println!("KEY_NAME");
-                } else if flag_argument == 0 {
-                    println!("KEY_END");
-                    break;
-                } else {
-                    break;
+                    builder = builder.meta(name, value);
                 }
+                Some(KeyNewFlags::KEY_FLAGS) => {
+                    let flags = KeyNewFlags::from_bits_truncate(unsafe { ap.next_arg::<c_int>() });
+
+                    if flags.contains(KeyNewFlags::KEY_BINARY) {
+                        binary = true;
+                        builder = builder.binary();
+                    }
+
+                    builder = builder.lock(KeyLockFlags::from_bits_truncate(flags.bits() as u32));
+                }
+                Some(flags) if KeyLockFlags::from_bits(flags.bits() as u32).is_some() => {
+                    builder = builder.lock(KeyLockFlags::from_bits_truncate(flags.bits() as u32));
+                }
+                _ => return ptr::null_mut(),
             }
+        }
 
-            return Box::into_raw(
-                Box::new(key.into())
-            );
+        match builder.build() {
+            Ok(key) => Box::into_raw(Box::new(key.into())),
+            Err(_) => ptr::null_mut(),
         }
+    })
+}
+
+/// Reads the value passed with `KEY_VALUE`. Binary values without a
+/// `KEY_SIZE` are read up to and including their terminating NUL, like in
+/// libelektra.
+fn new_value(value: *const c_void, binary: bool, size: Option<size_t>) -> KeyValue {
+    if value.is_null() {
+        return if binary { KeyValue::Binary(Vec::new()) } else { KeyValue::String(Vec::new()) };
     }
 
-    return ptr::null_mut();
+    if !binary {
+        return KeyValue::String(unsafe { CStr::from_ptr(value as *const c_char) }.to_bytes().to_vec());
+    }
+
+    let bytes = match size {
+        Some(size) => unsafe { slice::from_raw_parts(value as *const u8, size) },
+        None => unsafe { CStr::from_ptr(value as *const c_char) }.to_bytes_with_nul(),
+    };
+
+    KeyValue::Binary(bytes.to_vec())
+}
+
+/// # Safety
+///
+/// `dest` and `source` have to be NULL or valid keys.
+#[no_mangle]
+pub unsafe extern "C" fn keyCopy(dest: *mut CKey, source: *const CKey, flags: elektraCopyFlags) -> *mut CKey {
+    catch_panic(ptr::null_mut(), || {
+        if dest.is_null() {
+            return keyDup(source, flags);
+        }
+
+        if ptr::eq(dest, source) {
+            return dest;
+        }
+
+        let flags = KeyCopyFlags::from_bits_truncate(flags);
+
+        // copying from NULL resets the selected parts
+        let result = if source.is_null() {
+            let empty = Key::new(KeyName::new(KeyNamespace::Cascading, Vec::new()));
+            unsafe { &mut *dest }.update(|key| key.copy_from(&empty, flags))
+        } else {
+            let source = unsafe { &*source }.key();
+            unsafe { &mut *dest }.update(|key| key.copy_from(source, flags))
+        };
+
+        if result.is_err() {
+            return ptr::null_mut();
+        }
+
+        dest
+    })
 }
 
+/// # Safety
+///
+/// `source` has to be NULL or a valid key.
 #[no_mangle]
-pub extern "C" fn keyCopy(dest: *mut CKey, source: *const CKey, flags: elektraCopyFlags) -> *mut CKey {
-    &mut CKey::default()
+pub unsafe extern "C" fn keyDup(source: *const CKey, flags: elektraCopyFlags) -> *mut CKey {
+    catch_panic(ptr::null_mut(), || {
+        if source.is_null() {
+            return ptr::null_mut();
+        }
+
+        let mut key = Key::new(KeyName::new(KeyNamespace::Cascading, Vec::new()));
+
+        if key.copy_from(unsafe { &*source }.key(), KeyCopyFlags::from_bits_truncate(flags)).is_err() {
+            return ptr::null_mut();
+        }
+
+        Box::into_raw(
+            Box::new(key.into())
+        )
+    })
 }
 
+/// # Safety
+///
+/// `key` has to be NULL or a valid key.
 #[no_mangle]
 #[deprecated]
-pub extern "C" fn keyClear(key: *mut CKey) -> c_int {
-    1
+pub unsafe extern "C" fn keyClear(key: *mut CKey) -> c_int {
+    catch_panic(-1, || {
+        1
+    })
 }
 
+/// # Safety
+///
+/// `key` has to be NULL or a valid key.
 #[no_mangle]
-pub extern "C" fn keyDel(key: *mut CKey) -> c_int {
-    if key.is_null() {
-        return -1;
-    }
+pub unsafe extern "C" fn keyDel(key: *mut CKey) -> c_int {
+    catch_panic(-1, || {
+        if key.is_null() {
+            return -1;
+        }
+
+        let references = unsafe { (*key).ksReference };
+
+        if references > 0 {
+            return references as c_int;
+        }
+
+        CKey::destroy(key);
+
+        0
+    })
+}
 
-    CKey::destroy(key);
+/// # Safety
+///
+/// `key` has to be NULL or a valid key.
+#[no_mangle]
+pub unsafe extern "C" fn keyIncRef(key: *mut CKey) -> ssize_t {
+    catch_panic(-1, || {
+        if key.is_null() {
+            return -1;
+        }
 
-    return 0;
+        unsafe {
+            if (*key).ksReference >= ssize_t::MAX as size_t {
+                return -1;
+            }
+
+            (*key).ksReference += 1;
+            (*key).ksReference as ssize_t
+        }
+    })
 }
 
+/// # Safety
+///
+/// `key` has to be NULL or a valid key.
 #[no_mangle]
-pub extern "C" fn keyCopyMeta(
+pub unsafe extern "C" fn keyDecRef(key: *mut CKey) -> ssize_t {
+    catch_panic(-1, || {
+        if key.is_null() {
+            return -1;
+        }
+
+        unsafe {
+            if (*key).ksReference > 0 {
+                (*key).ksReference -= 1;
+            }
+
+            (*key).ksReference as ssize_t
+        }
+    })
+}
+
+/// # Safety
+///
+/// `key` has to be NULL or a valid key.
+#[no_mangle]
+pub unsafe extern "C" fn keyGetRef(key: *const CKey) -> ssize_t {
+    catch_panic(-1, || {
+        if key.is_null() {
+            return -1;
+        }
+
+        unsafe { (*key).ksReference as ssize_t }
+    })
+}
+
+/// # Safety
+///
+/// `dest` and `source` have to be NULL or valid keys and `metaName` NULL or a
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn keyCopyMeta(
     dest: *mut CKey,
     source: *const CKey,
     metaName: *const c_char,
 ) -> c_int {
-    1
+    catch_panic(-1, || {
+        if dest.is_null() || source.is_null() || metaName.is_null() {
+            return -1;
+        }
+
+        let metaNameStr = unsafe { CStr::from_ptr(metaName) }.to_bytes();
+
+        if ptr::eq(dest, source) {
+            return unsafe { &*source }.key().meta(metaNameStr).is_some() as c_int;
+        }
+
+        let source_key = unsafe { &*source }.key();
+
+        if unsafe { &mut *dest }.update(|key| key.copy_meta_from(source_key, metaNameStr)).is_err() {
+            return -1;
+        }
+
+        return source_key.meta(metaNameStr).is_some() as c_int;
+    })
 }
 
+/// # Safety
+///
+/// `dest` and `source` have to be NULL or valid keys.
 #[no_mangle]
-pub extern "C" fn keyCopyAllMeta(dest: *mut CKey, source: *const CKey) -> c_int {
-    1
+pub unsafe extern "C" fn keyCopyAllMeta(dest: *mut CKey, source: *const CKey) -> c_int {
+    catch_panic(-1, || {
+        if dest.is_null() || source.is_null() {
+            return -1;
+        }
+
+        let source_key = unsafe { &*source }.key();
+
+        if source_key.meta_keys().size() == 0 {
+            return 0;
+        }
+
+        if ptr::eq(dest, source) {
+            return 1;
+        }
+
+        if unsafe { &mut *dest }.update(|key| key.copy_all_meta_from(source_key)).is_err() {
+            return -1;
+        }
+
+        1
+    })
 }
 
+/// # Safety
+///
+/// `key` has to be NULL or a valid key and `metaName` NULL or a
+/// NUL-terminated string.
 #[no_mangle]
-pub extern "C" fn keyMeta(key: *mut CKey) -> *mut CKeySet {
-    unsafe {
-        (*key).meta
-    }
+pub unsafe extern "C" fn keyGetMeta(key: *const CKey, metaName: *const c_char) -> *const CKey {
+    catch_panic(ptr::null(), || {
+        if key.is_null() || metaName.is_null() {
+            return ptr::null();
+        }
+
+        let metaNameStr = unsafe { CStr::from_ptr(metaName) }.to_bytes();
+
+        let meta_name = match unsafe { &*key }.key().meta(metaNameStr) {
+            Some(meta_key) => meta_key.key_name().clone(),
+            None => return ptr::null(),
+        };
+
+        let meta = unsafe { &*CKey::meta(key as *mut CKey) };
+
+        match meta.search(&meta_name) {
+            Ok(position) => meta.keys()[position],
+            Err(_) => ptr::null(),
+        }
+    })
 }
 
+/// # Safety
+///
+/// `key` has to be NULL or a valid key and `metaName` and `newMetaString`
+/// NULL or NUL-terminated strings.
 #[no_mangle]
-pub extern "C" fn keyCmp(k1: *const CKey, k2: *const CKey) -> c_int {
-    if k1.is_null() || k2.is_null() {
-        return -1;
-    }
+pub unsafe extern "C" fn keySetMeta(
+    key: *mut CKey,
+    metaName: *const c_char,
+    newMetaString: *const c_char,
+) -> ssize_t {
+    catch_panic(-1, || {
+        if key.is_null() || metaName.is_null() {
+            return -1;
+        }
 
-    let k1 = unsafe { &*k1 };
-    let k2 = unsafe { &*k2 };
+        let metaNameStr = unsafe { CStr::from_ptr(metaName) }.to_bytes();
 
-    let that_key = match Key::try_from(k1) {
-        Ok(x) => x,
-        Err(_) => return -1,
-    };
+        let key = unsafe { &mut *key };
 
-    let other_key = match Key::try_from(k2) {
-        Ok(x) => x,
-        Err(_) => return -1,
-    };
+        if newMetaString.is_null() {
+            if key.update(|key| key.remove_meta(metaNameStr)).is_err() {
+                return -1;
+            }
 
-    return that_key.cmp(&other_key) as c_int;
-}
+            return 0;
+        }
 
+        let cstr = unsafe { CStr::from_ptr(newMetaString) };
 
-#[no_mangle]
-pub extern "C" fn keyNeedSync(key: *const CKey) -> c_int {
-    1
+        if key.update(|key| key.set_meta(metaNameStr, cstr.to_bytes())).is_err() {
+            return -1;
+        }
+
+        cstr.to_bytes_with_nul().len() as ssize_t
+    })
 }
 
+/// Returns the metadata of `key`, as a key set that lives as long as the
+/// key and cannot be deleted with `ksDel`. Meta keys added to or removed
+/// from it change the metadata of the key, unless it is locked.
+///
+/// # Safety
+///
+/// `key` has to be NULL or a valid key.
 #[no_mangle]
-pub extern "C" fn keyIsBelow(key: *mut CKey, check: *mut CKey) -> c_int {
-    if key.is_null() || check.is_null() {
-        return -1;
-    }
+pub unsafe extern "C" fn keyMeta(key: *mut CKey) -> *mut CKeySet {
+    catch_panic(ptr::null_mut(), || {
+        if key.is_null() {
+            return ptr::null_mut();
+        }
 
-    let key1 = unsafe { &*key };
-    let key2 = unsafe { &*check };
+        CKey::meta(key)
+    })
+}
 
-    let that_key = match Key::try_from(key1) {
-        Ok(x) => x,
-        Err(_) => return -1,
-    };
+/// # Safety
+///
+/// `k1` and `k2` have to be NULL or valid keys.
+#[no_mangle]
+pub unsafe extern "C" fn keyCmp(k1: *const CKey, k2: *const CKey) -> c_int {
+    catch_panic(-1, || {
+        match (k1.is_null(), k2.is_null()) {
+            (true, true) => return 0,
+            (true, false) => return -1,
+            (false, true) => return 1,
+            (false, false) => {}
+        }
 
-    let other_key = match Key::try_from(key2) {
-        Ok(x) => x,
-        Err(_) => return -1,
-    };
+        let that_key = unsafe { &*k1 }.key();
+        let other_key = unsafe { &*k2 }.key();
 
-    return match that_key.cmp(&other_key) {
-        Ordering::Equal | Ordering::Greater => 0,
-        Ordering::Less => 1,
-    };
+        that_key.cmp(other_key) as c_int
+    })
 }
 
+
+/// # Safety
+///
+/// `key` has to be NULL or a valid key.
 #[no_mangle]
-pub extern "C" fn keyIsBelowOrSame(key: *mut CKey, check: *mut CKey) -> c_int {
+pub unsafe extern "C" fn keyNeedSync(key: *const CKey) -> c_int {
+    catch_panic(-1, || {
+        1
+    })
+}
+
+/// Evaluates `relation(check, key)` on the Rust representation of both keys.
+fn keyRelation(key: *const CKey, check: *const CKey, relation: fn(&Key, &Key) -> bool) -> c_int {
     if key.is_null() || check.is_null() {
         return -1;
     }
 
-    let key1 = unsafe { &*key };
-    let key2 = unsafe { &*check };
-
-    let that_key = match Key::try_from(key1) {
-        Ok(x) => x,
-        Err(_) => return -1,
-    };
+    let that_key = unsafe { &*key }.key();
+    let other_key = unsafe { &*check }.key();
 
-    let other_key = match Key::try_from(key2) {
-        Ok(x) => x,
-        Err(_) => return -1,
-    };
+    relation(other_key, that_key) as c_int
+}
 
-    return match that_key.cmp(&other_key) {
-        Ordering::Greater => 0,
-        Ordering::Less | Ordering::Equal => 1,
-    };
+/// # Safety
+///
+/// `key` and `check` have to be NULL or valid keys.
+#[no_mangle]
+pub unsafe extern "C" fn keyIsBelow(key: *mut CKey, check: *mut CKey) -> c_int {
+    catch_panic(-1, || {
+        keyRelation(key, check, Key::is_below)
+    })
 }
 
+/// # Safety
+///
+/// `key` and `check` have to be NULL or valid keys.
 #[no_mangle]
-pub extern "C" fn keyIsDirectlyBelow(key: *const CKey, check: *const CKey) -> c_int {
-    1
+pub unsafe extern "C" fn keyIsBelowOrSame(key: *mut CKey, check: *mut CKey) -> c_int {
+    catch_panic(-1, || {
+        keyRelation(key, check, Key::is_below_or_same)
+    })
 }
 
+/// # Safety
+///
+/// `key` and `check` have to be NULL or valid keys.
 #[no_mangle]
-pub extern "C" fn keyName(key: *const CKey) -> *const c_char {
-    unsafe {
-        (*key).key
-    }
+pub unsafe extern "C" fn keyIsDirectlyBelow(key: *const CKey, check: *const CKey) -> c_int {
+    catch_panic(-1, || {
+        keyRelation(key, check, Key::is_directly_below)
+    })
 }
 
+/// # Safety
+///
+/// `key` has to be NULL or a valid key.
 #[no_mangle]
-pub extern "C" fn keyGetNameSize(key: *const CKey) -> ssize_t {
-    let cstr = unsafe { CStr::from_ptr((*key).key) };
+pub unsafe extern "C" fn keyName(key: *const CKey) -> *const c_char {
+    catch_panic(ptr::null(), || {
+        if key.is_null() {
+            return ptr::null();
+        }
 
-    cstr.to_bytes_with_nul().len() as ssize_t
+        unsafe { &*key }.name().as_ptr()
+    })
 }
 
+/// # Safety
+///
+/// `key` has to be NULL or a valid key.
 #[no_mangle]
-pub extern "C" fn keySetName(key: *mut CKey, newname: *const c_char) -> ssize_t {
-    if key.is_null() || newname.is_null() {
-        return -1;
-    }
+pub unsafe extern "C" fn keyGetNameSize(key: *const CKey) -> ssize_t {
+    catch_panic(-1, || {
+        if key.is_null() {
+            return -1;
+        }
 
-    let cstr = unsafe { CStr::from_ptr(newname) };
-    let newNameStr = match cstr.to_str() {
-        Ok(x) => x,
-        Err(_) => return -1,
-    };
+        unsafe { &*key }.name().to_bytes_with_nul().len() as ssize_t
+    })
+}
 
-    if let Ok(key_name) = KeyName::from_str(newNameStr) {
-        let key1 = unsafe { &*key };
+/// # Safety
+///
+/// `key` has to be NULL or a valid key and `newname` NULL or a NUL-terminated
+/// string.
+#[no_mangle]
+pub unsafe extern "C" fn keySetName(key: *mut CKey, newname: *const c_char) -> ssize_t {
+    catch_panic(-1, || {
+        if key.is_null() || newname.is_null() {
+            return -1;
+        }
 
-        let mut rust_key = match Key::try_from(key1) {
+        let key_name = match KeyName::from_bytes(unsafe { CStr::from_ptr(newname) }.to_bytes()) {
             Ok(x) => x,
             Err(_) => return -1,
         };
 
-        rust_key.set_name(key_name);
-        CKey::overwrite(key, rust_key);
-        return keyGetNameSize(key);
-    }
+        if unsafe { &mut *key }.update(|key| key.set_name(key_name)).is_err() {
+            return -1;
+        }
 
-    return -1;
+        keyGetNameSize(key)
+    })
 }
 
+/// # Safety
+///
+/// `key` has to be NULL or a valid key and `addName` NULL or a NUL-terminated
+/// string.
 #[no_mangle]
-pub extern "C" fn keyAddName(key: *mut CKey, addName: *const c_char) -> ssize_t {
-    let cstr = unsafe { CStr::from_ptr(addName) };
-    let addNameStr = match cstr.to_str() {
-        Ok(x) => x,
-        Err(_) => return -1,
-    };
+pub unsafe extern "C" fn keyAddName(key: *mut CKey, addName: *const c_char) -> ssize_t {
+    catch_panic(-1, || {
+        if key.is_null() || addName.is_null() {
+            return -1;
+        }
 
-    let key1 = unsafe { &*key };
-    let mut rust_key = match Key::try_from(key1) {
-        Ok(x) => x,
-        Err(_) => return -1,
-    };
+        let addName = unsafe { CStr::from_ptr(addName) }.to_bytes();
 
-    rust_key.append_name(addNameStr);
-    CKey::overwrite(key, rust_key);
-    return keyGetNameSize(key);
+        if unsafe { &mut *key }.update(|key| key.append_name(addName)).is_err() {
+            return -1;
+        }
+
+        keyGetNameSize(key)
+    })
 }
 
+/// # Safety
+///
+/// `key` has to be NULL or a valid key.
 #[no_mangle]
-pub extern "C" fn keyUnescapedName(key: *const CKey) -> *const c_void {
-    ptr::null_mut()
+pub unsafe extern "C" fn keyUnescapedName(key: *const CKey) -> *const c_void {
+    catch_panic(ptr::null(), || {
+        if key.is_null() {
+            return ptr::null_mut();
+        }
+
+        unsafe { &*key }.unescaped_name().as_ptr() as *const c_void
+    })
 }
 
+/// # Safety
+///
+/// `key` has to be NULL or a valid key.
 #[no_mangle]
-pub extern "C" fn keyGetUnescapedNameSize(key: *const CKey) -> ssize_t {
-    1
+pub unsafe extern "C" fn keyGetUnescapedNameSize(key: *const CKey) -> ssize_t {
+    catch_panic(-1, || {
+        if key.is_null() {
+            return -1;
+        }
+
+        unsafe { &*key }.unescaped_name().len() as ssize_t
+    })
 }
 
+/// # Safety
+///
+/// `key` has to be NULL or a valid key.
 #[no_mangle]
-pub extern "C" fn keyBaseName(key: *const CKey) -> *const c_char {
-    CString::new("qq").expect("CString new failed").into_raw()
+pub unsafe extern "C" fn keyBaseName(key: *const CKey) -> *const c_char {
+    catch_panic(ptr::null(), || {
+        if key.is_null() {
+            return ptr::null();
+        }
+
+        unsafe { &*key }.base_name().as_ptr()
+    })
 }
 
+/// # Safety
+///
+/// `key` has to be NULL or a valid key and `baseName` NULL or a
+/// NUL-terminated string.
 #[no_mangle]
-pub extern "C" fn keySetBaseName(key: *mut CKey, baseName: *const c_char) -> ssize_t {
-    1
+pub unsafe extern "C" fn keySetBaseName(key: *mut CKey, baseName: *const c_char) -> ssize_t {
+    catch_panic(-1, || {
+        1
+    })
 }
 
+/// # Safety
+///
+/// `key` has to be NULL or a valid key and `baseName` NULL or a
+/// NUL-terminated string.
 #[no_mangle]
-pub extern "C" fn keyAddBaseName(key: *mut CKey, baseName: *const c_char) -> ssize_t {
-    let cstr = unsafe { CStr::from_ptr(baseName) };
-    let addNameStr = match cstr.to_str() {
-        Ok(x) => x,
-        Err(_) => return -1,
-    };
+pub unsafe extern "C" fn keyAddBaseName(key: *mut CKey, baseName: *const c_char) -> ssize_t {
+    catch_panic(-1, || {
+        if key.is_null() || baseName.is_null() {
+            return -1;
+        }
 
-    let key1 = unsafe { &*key };
-    let mut rust_key = match Key::try_from(key1) {
-        Ok(x) => x,
-        Err(_) => return -1,
-    };
+        let baseName = unsafe { CStr::from_ptr(baseName) }.to_bytes();
 
-    rust_key.append_name(addNameStr);
-    CKey::overwrite(key, rust_key);
-    return keyGetNameSize(key);
+        if unsafe { &mut *key }.update(|key| key.append_base_name(baseName)).is_err() {
+            return -1;
+        }
+
+        keyGetNameSize(key)
+    })
 }
 
+/// # Safety
+///
+/// `key` has to be NULL or a valid key.
 #[no_mangle]
-pub extern "C" fn keyGetNamespace(key: *const CKey) -> elektraNamespace {
-    if key.is_null() {
-        return elektraNamespace::KEY_NS_NONE;
-    }
+pub unsafe extern "C" fn keyGetNamespace(key: *const CKey) -> elektraNamespace {
+    catch_panic(KEY_NS_NONE, || {
+        if key.is_null() {
+            return KEY_NS_NONE;
+        }
 
-    let key1 = unsafe { &*key };
-    let rust_key = match Key::try_from(key1) {
-        Ok(x) => x,
-        Err(_) => return KEY_NS_NONE,
-    };
+        elektraNamespace::from(unsafe { &*key }.key().namespace())
+    })
+}
+
+/// # Safety
+///
+/// `key` has to be NULL or a valid key.
+#[no_mangle]
+pub unsafe extern "C" fn keySetNamespace(key: *mut CKey, ns: elektraNamespace) -> ssize_t {
+    catch_panic(-1, || {
+        if key.is_null() {
+            return -1;
+        }
+
+        if unsafe { &mut *key }.update(|key| key.set_namespace(KeyNamespace::from(ns))).is_err() {
+            return -1;
+        }
 
-    elektraNamespace::from(rust_key.namespace())
+        keyGetNameSize(key)
+    })
 }
 
+const EMPTY_STRING: &[u8] = b"\0";
+const NULL_STRING: &[u8] = b"(null)\0";
+const BINARY_STRING: &[u8] = b"(binary)\0";
+
+/// # Safety
+///
+/// `key` has to be NULL or a valid key.
 #[no_mangle]
-pub extern "C" fn keySetNamespace(key: *mut CKey, ns: elektraNamespace) -> ssize_t {
-    let key1 = unsafe { &*key };
-    let mut rust_key = match Key::try_from(key1) {
-        Ok(x) => x,
-        Err(_) => return -1,
-    };
+pub unsafe extern "C" fn keyValue(key: *const CKey) -> *const c_void {
+    catch_panic(ptr::null(), || {
+        if key.is_null() {
+            return ptr::null();
+        }
 
-    let namespace = KeyNamespace::from(ns);
-    rust_key.set_namespace(namespace);
+        if let Some(value) = unsafe { &*key }.value() {
+            return value.as_ptr() as *const c_void;
+        }
 
-    namespace.to_string().len() as ssize_t
+        match keyIsBinary(key) {
+            0 => EMPTY_STRING.as_ptr() as *const c_void,
+            _ => ptr::null(),
+        }
+    })
 }
 
+/// # Safety
+///
+/// `key` has to be NULL or a valid key.
 #[no_mangle]
-pub extern "C" fn keyValue(key: *const CKey) -> *const c_void {
-    let key1 = unsafe { &*key };
-    let rust_key = match Key::try_from(key1) {
-        Ok(x) => x,
-        Err(_) => return ptr::null_mut(),
-    };
+pub unsafe extern "C" fn keyString(key: *const CKey) -> *const c_char {
+    catch_panic(NULL_STRING.as_ptr() as *const c_char, || {
+        if key.is_null() {
+            return NULL_STRING.as_ptr() as *const c_char;
+        }
 
-    if let Some(value) = rust_key.value() {
-        println!("{:?}", value);
-        return value.as_ptr() as *const c_void;
-    }
+        if keyIsBinary(key) == 1 {
+            return BINARY_STRING.as_ptr() as *const c_char;
+        }
 
-    return ptr::null_mut();
+        match unsafe { &*key }.value() {
+            Some(value) => value.as_ptr() as *const c_char,
+            None => EMPTY_STRING.as_ptr() as *const c_char,
+        }
+    })
 }
 
+/// # Safety
+///
+/// `key` has to be NULL or a valid key.
 #[no_mangle]
-pub extern "C" fn keySetBinary(key: *mut CKey, newBinary: *const c_void, size: size_t) -> ssize_t {
-    let key1 = unsafe { &*key };
-    let mut rust_key = match Key::try_from(key1) {
-        Ok(x) => x,
-        Err(_) => return -1,
-    };
+pub unsafe extern "C" fn keyGetValueSize(key: *const CKey) -> ssize_t {
+    catch_panic(-1, || {
+        if key.is_null() {
+            return -1;
+        }
 
-    let newValue = unsafe {
-        slice::from_raw_parts(newBinary as *const u8, size)
-    };
+        // a string without a value is the empty string, which still has its NUL
+        match unsafe { &*key }.value() {
+            Some(value) => value.len() as ssize_t,
+            None if keyIsBinary(key) == 0 => EMPTY_STRING.len() as ssize_t,
+            None => 0,
+        }
+    })
+}
 
-    rust_key.set_value(newValue.to_vec());
+/// # Safety
+///
+/// `key` has to be NULL or a valid key.
+#[no_mangle]
+pub unsafe extern "C" fn keyValueSize(key: *const CKey) -> ssize_t {
+    catch_panic(-1, || {
+        keyGetValueSize(key)
+    })
+}
 
-    println!("{:?}", rust_key.value().unwrap());
+/// # Safety
+///
+/// `key` has to be NULL or a valid key.
+#[no_mangle]
+pub unsafe extern "C" fn keyIsBinary(key: *const CKey) -> c_int {
+    catch_panic(-1, || {
+        if key.is_null() {
+            return -1;
+        }
 
-    size as ssize_t
+        unsafe { &*key }.key().is_binary() as c_int
+    })
 }
 
+/// # Safety
+///
+/// `key` has to be NULL or a valid key.
 #[no_mangle]
-pub extern "C" fn keyValueSize(key: *const CKey) -> ssize_t {
-    let key1 = unsafe { &*key };
-    let rust_key = match Key::try_from(key1) {
-        Ok(x) => x,
-        Err(_) => return -1,
-    };
+pub unsafe extern "C" fn keyIsString(key: *const CKey) -> c_int {
+    catch_panic(-1, || {
+        match keyIsBinary(key) {
+            -1 => -1,
+            binary => (binary == 0) as c_int,
+        }
+    })
+}
 
-    if let Some(value) = rust_key.value() {
-        return value.len() as ssize_t
-    }
+/// # Safety
+///
+/// `key` has to be NULL or a valid key and `returnedString` NULL or writable
+/// for `maxSize` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn keyGetString(key: *const CKey, returnedString: *mut c_char, maxSize: size_t) -> ssize_t {
+    catch_panic(-1, || {
+        if key.is_null() || returnedString.is_null() || maxSize == 0 || keyIsString(key) != 1 {
+            return -1;
+        }
 
-    return 0;
+        let value = match unsafe { &*key }.value() {
+            Some(x) => x,
+            None => {
+                unsafe { *returnedString = 0 };
+                return 1;
+            }
+        };
+
+        if value.len() > maxSize {
+            return -1;
+        }
+
+        unsafe {
+            ptr::copy_nonoverlapping(value.as_ptr() as *const c_char, returnedString, value.len());
+        }
+
+        value.len() as ssize_t
+    })
 }
 
+/// # Safety
+///
+/// `key` has to be NULL or a valid key and `newString` NULL or a
+/// NUL-terminated string.
 #[no_mangle]
-pub extern "C" fn keyString(key: *const CKey) -> *const c_char {
-    CString::new("qq").expect("CString new failed").into_raw()
+pub unsafe extern "C" fn keySetString(key: *mut CKey, newString: *const c_char) -> ssize_t {
+    catch_panic(-1, || {
+        if key.is_null() {
+            return -1;
+        }
+
+        let newValue = if newString.is_null() {
+            Vec::new()
+        } else {
+            unsafe { CStr::from_ptr(newString) }.to_bytes().to_vec()
+        };
+
+        if unsafe { &mut *key }.update(|key| key.set_value(KeyValue::String(newValue))).is_err() {
+            return -1;
+        }
+
+        keyGetValueSize(key)
+    })
 }
 
+/// # Safety
+///
+/// `key` has to be NULL or a valid key and `returnedBinary` NULL or writable
+/// for `maxSize` bytes.
 #[no_mangle]
-pub extern "C" fn keyLock(key: *mut CKey, what: elektraLockFlags) -> c_int {
-    1
+pub unsafe extern "C" fn keyGetBinary(key: *const CKey, returnedBinary: *mut c_void, maxSize: size_t) -> ssize_t {
+    catch_panic(-1, || {
+        if key.is_null() || returnedBinary.is_null() || maxSize == 0 || keyIsBinary(key) != 1 {
+            return -1;
+        }
+
+        let value = unsafe { &*key }.value().unwrap_or_default();
+
+        if value.len() > maxSize {
+            return -1;
+        }
+
+        unsafe {
+            ptr::copy_nonoverlapping(value.as_ptr(), returnedBinary as *mut u8, value.len());
+        }
+
+        value.len() as ssize_t
+    })
 }
 
+/// # Safety
+///
+/// `key` has to be NULL or a valid key and `newBinary` NULL or readable for
+/// `size` bytes.
 #[no_mangle]
-pub extern "C" fn keyIsLocked(key: *const CKey, what: elektraLockFlags) -> c_int {
-    1
+pub unsafe extern "C" fn keySetBinary(key: *mut CKey, newBinary: *const c_void, size: size_t) -> ssize_t {
+    catch_panic(-1, || {
+        if key.is_null() || (newBinary.is_null() && size != 0) {
+            return -1;
+        }
+
+        let newValue = if newBinary.is_null() {
+            Vec::new()
+        } else {
+            unsafe { slice::from_raw_parts(newBinary as *const u8, size) }.to_vec()
+        };
+
+        if unsafe { &mut *key }.update(|key| key.set_value(KeyValue::Binary(newValue))).is_err() {
+            return -1;
+        }
+
+        keyGetValueSize(key)
+    })
 }
 
+/// # Safety
+///
+/// `key` has to be NULL or a valid key.
 #[no_mangle]
-pub extern "C" fn ksNew(alloc: size_t) -> *mut CKeySet {
-    let ks = KeySet::default();
-    &mut ks.into()
+pub unsafe extern "C" fn keyLock(key: *mut CKey, what: elektraLockFlags) -> c_int {
+    catch_panic(-1, || {
+        if key.is_null() {
+            return -1;
+        }
+
+        let what = KeyLockFlags::from_bits_truncate(what as u32);
+        unsafe { &mut *key }.update(|key| key.lock(what));
+
+        keyIsLocked(key, what.bits() as elektraLockFlags)
+    })
 }
 
+/// # Safety
+///
+/// `key` has to be NULL or a valid key.
 #[no_mangle]
-pub extern "C" fn ksDup(source: *const CKeySet) -> *mut CKeySet {
-    &mut CKeySet::default()
+pub unsafe extern "C" fn keyIsLocked(key: *const CKey, what: elektraLockFlags) -> c_int {
+    catch_panic(-1, || {
+        if key.is_null() {
+            return -1;
+        }
+
+        let locks = unsafe { &*key }.key().locks();
+
+        (locks & KeyLockFlags::from_bits_truncate(what as u32)).bits() as c_int
+    })
 }
 
+/// # Safety
+///
+/// The arguments have to be valid keys, ending with `KS_END`.
 #[no_mangle]
-pub extern "C" fn ksCopy(dest: *mut CKeySet, source: *const CKeySet) -> c_int {
-    1
+pub unsafe extern "C" fn ksNew(alloc: size_t, args: ...) -> *mut CKeySet {
+    ksVNew(alloc, args)
 }
 
+/// # Safety
+///
+/// `ap` has to hold valid keys, ending with `KS_END`.
 #[no_mangle]
-pub extern "C" fn ksClear(ks: *mut CKeySet) -> c_int {
-    1
+pub unsafe extern "C" fn ksVNew(alloc: size_t, mut ap: VaList) -> *mut CKeySet {
+    catch_panic(ptr::null_mut(), || {
+        let ks = Box::into_raw(
+            Box::new(CKeySet::from_keys(Vec::with_capacity(alloc)))
+        );
+
+        loop {
+            let key = unsafe { ap.next_arg::<*mut CKey>() };
+
+            if key.is_null() {
+                break;
+            }
+
+            ksAppendKey(ks, key);
+        }
+
+        unsafe { &mut *ks }.rewind();
+
+        ks
+    })
 }
 
+/// # Safety
+///
+/// `source` has to be NULL or a valid key set.
 #[no_mangle]
-pub extern "C" fn ksDel(ks: *mut CKeySet) -> c_int {
-    1
+pub unsafe extern "C" fn ksDup(source: *const CKeySet) -> *mut CKeySet {
+    catch_panic(ptr::null_mut(), || {
+        if source.is_null() {
+            return ptr::null_mut();
+        }
+
+        let keys = unsafe { &*source }.keys().to_vec();
+
+        for &key in &keys {
+            unsafe { (*(key as *mut CKey)).ksReference += 1 };
+        }
+
+        Box::into_raw(
+            Box::new(CKeySet::from_keys(keys))
+        )
+    })
+}
+
+/// # Safety
+///
+/// `dest` and `source` have to be NULL or valid key sets.
+#[no_mangle]
+pub unsafe extern "C" fn ksCopy(dest: *mut CKeySet, source: *const CKeySet) -> c_int {
+    catch_panic(-1, || {
+        if dest.is_null() {
+            return -1;
+        }
+
+        if ptr::eq(dest, source) {
+            return 1;
+        }
+
+        let accepted = match unsafe { source.as_ref() } {
+            Some(source) => source.keys().iter().all(|&key| unsafe { &*dest }.accepts(unsafe { &*key })),
+            None => unsafe { &*dest }.is_writable(),
+        };
+
+        if !accepted {
+            return -1;
+        }
+
+        unsafe { &mut *dest }.clear();
+
+        if source.is_null() {
+            return if write_back(dest) { 0 } else { -1 };
+        }
+
+        ksAppend(dest, source);
+        unsafe { &mut *dest }.rewind();
+
+        if write_back(dest) { 1 } else { -1 }
+    })
+}
+
+/// # Safety
+///
+/// `ks` has to be NULL or a valid key set.
+#[no_mangle]
+pub unsafe extern "C" fn ksClear(ks: *mut CKeySet) -> c_int {
+    catch_panic(-1, || {
+        if ks.is_null() {
+            return -1;
+        }
+
+        if !unsafe { &*ks }.is_writable() {
+            return -1;
+        }
+
+        unsafe { &mut *ks }.clear();
+
+        if write_back(ks) { 0 } else { -1 }
+    })
+}
+
+/// # Safety
+///
+/// `ks` has to be NULL or a valid key set.
+#[no_mangle]
+pub unsafe extern "C" fn ksDel(ks: *mut CKeySet) -> c_int {
+    catch_panic(-1, || {
+        if ks.is_null() {
+            return -1;
+        }
+
+        let references = unsafe { (*ks).refs };
+
+        if references > 0 {
+            return references as c_int;
+        }
+
+        CKeySet::destroy(ks);
+
+        0
+    })
+}
+
+/// # Safety
+///
+/// `ks` has to be NULL or a valid key set.
+#[no_mangle]
+pub unsafe extern "C" fn ksGetSize(ks: *const CKeySet) -> ssize_t {
+    catch_panic(-1, || {
+        if ks.is_null() {
+            return -1;
+        }
+
+        (unsafe { (*ks).size } as ssize_t)
+    })
+}
+
+/// Inserts `toAppend` at its sorted position, replacing a key with the
+/// same name, and moves the cursor to it. The name of the key gets locked,
+/// and also its value and metadata if it is added to the metadata of a key.
+///
+/// # Safety
+///
+/// `ks` has to be NULL or a valid key set and `toAppend` NULL or a valid key.
+#[no_mangle]
+pub unsafe extern "C" fn ksAppendKey(ks: *mut CKeySet, toAppend: *mut CKey) -> ssize_t {
+    catch_panic(-1, || {
+        if ks.is_null() || toAppend.is_null() {
+            return -1;
+        }
+
+        let ks = unsafe { &mut *ks };
+
+        if !ks.accepts(unsafe { &*toAppend }) {
+            return -1;
+        }
+
+        let found = ks.search(unsafe { &*toAppend }.key().key_name());
+
+        let replaced = ks.with_keys(|keys| {
+            match found {
+                Ok(position) => std::mem::replace(&mut keys[position], toAppend),
+                Err(position) => {
+                    keys.insert(position, toAppend);
+                    ptr::null()
+                }
+            }
+        });
+
+        let toAppend = unsafe { &mut *toAppend };
+        toAppend.ksReference += 1;
+        let locks = if ks.owner.is_null() { KeyLockFlags::KEY_LOCK_NAME } else { META_KEY_LOCKS };
+        toAppend.update(|key| key.lock(locks));
+
+        if !replaced.is_null() {
+            CKey::release(replaced as *mut CKey);
+        }
+
+        ks.set_cursor(found.unwrap_or_else(|position| position));
+
+        if write_back(ks) { ks.size as ssize_t } else { -1 }
+    })
+}
+
+/// # Safety
+///
+/// `ks` and `toAppend` have to be NULL or valid key sets.
+#[no_mangle]
+pub unsafe extern "C" fn ksAppend(ks: *mut CKeySet, toAppend: *const CKeySet) -> ssize_t {
+    catch_panic(-1, || {
+        if ks.is_null() || toAppend.is_null() {
+            return -1;
+        }
+
+        for key in unsafe { &*toAppend }.keys().to_vec() {
+            if ksAppendKey(ks, key as *mut CKey) < 0 {
+                return -1;
+            }
+        }
+
+        (unsafe { (*ks).size } as ssize_t)
+    })
 }
 
+/// # Safety
+///
+/// `ks` has to be NULL or a valid key set and `cutpoint` NULL or a valid key.
 #[no_mangle]
-pub extern "C" fn ksGetSize(ks: *const CKeySet) -> ssize_t {
-    1
+pub unsafe extern "C" fn ksCut(ks: *mut CKeySet, cutpoint: *const CKey) -> *mut CKeySet {
+    catch_panic(ptr::null_mut(), || {
+        if ks.is_null() || cutpoint.is_null() {
+            return ptr::null_mut();
+        }
+
+        if !unsafe { &*ks }.is_writable() {
+            return ptr::null_mut();
+        }
+
+        let cutpoint = unsafe { &*cutpoint }.key().key_name();
+
+        let keys = unsafe { &*ks }.keys();
+
+        let below = keys.iter()
+            .map(|&key| Key::new(unsafe { &*key }.key().key_name().clone()))
+            .collect::<KeySet>()
+            .below(cutpoint);
+
+        let has_cursor = !unsafe { &*ks }.cursor.is_null();
+        let current = unsafe { &*ks }.current;
+
+        let mut cut = Vec::new();
+        let mut keep = Vec::new();
+        let mut cursor = None;
+
+        for (position, &key) in keys.iter().enumerate() {
+            let is_below = below.contains(unsafe { &*key }.key().key_name());
+
+            if is_below {
+                cut.push(key);
+            } else {
+                keep.push(key);
+            }
+
+            // Like libelektra, a cursor on a cut key moves to the key before it.
+            if has_cursor && position == current {
+                cursor = keep.len().checked_sub(1);
+            }
+        }
+
+        let ks = unsafe { &mut *ks };
+        ks.with_keys(|keys| *keys = keep);
+
+        match cursor {
+            Some(position) => ks.set_cursor(position),
+            None => ks.rewind(),
+        }
+
+        let cut = Box::into_raw(Box::new(CKeySet::from_keys(cut)));
+
+        if !write_back(ks) {
+            ksDel(cut);
+            return ptr::null_mut();
+        }
+
+        cut
+    })
+}
+
+/// Removes the last key and hands its reference over to the caller.
+///
+/// # Safety
+///
+/// `ks` has to be NULL or a valid key set.
+#[no_mangle]
+pub unsafe extern "C" fn ksPop(ks: *mut CKeySet) -> *mut CKey {
+    catch_panic(ptr::null_mut(), || {
+        if ks.is_null() {
+            return ptr::null_mut();
+        }
+
+        let ks = unsafe { &mut *ks };
+
+        if !ks.is_writable() {
+            return ptr::null_mut();
+        }
+
+        let key = match ks.with_keys(|keys| keys.pop()) {
+            Some(x) => x as *mut CKey,
+            None => return ptr::null_mut(),
+        };
+
+        unsafe { (*key).ksReference -= 1 };
+        ks.rewind();
+
+        if !write_back(ks) {
+            keyDel(key);
+            return ptr::null_mut();
+        }
+
+        key
+    })
+}
+
+/// # Safety
+///
+/// `ks` has to be NULL or a valid key set.
+#[no_mangle]
+pub unsafe extern "C" fn ksRewind(ks: *mut CKeySet) -> c_int {
+    catch_panic(-1, || {
+        if ks.is_null() {
+            return -1;
+        }
+
+        unsafe { &mut *ks }.rewind();
+
+        0
+    })
+}
+
+/// # Safety
+///
+/// `ks` has to be NULL or a valid key set.
+#[no_mangle]
+pub unsafe extern "C" fn ksNext(ks: *mut CKeySet) -> *mut CKey {
+    catch_panic(ptr::null_mut(), || {
+        if ks.is_null() {
+            return ptr::null_mut();
+        }
+
+        let ks = unsafe { &mut *ks };
+
+        if !ks.cursor.is_null() {
+            ks.current += 1;
+        }
+
+        if ks.current >= ks.size {
+            ks.cursor = ptr::null_mut();
+            return ptr::null_mut();
+        }
+
+        ks.set_cursor(ks.current);
+
+        ks.cursor
+    })
 }
 
+/// # Safety
+///
+/// `ks` has to be NULL or a valid key set.
 #[no_mangle]
-pub extern "C" fn ksAppendKey(ks: *mut CKeySet, toAppend: *mut CKey) -> ssize_t {
-    1
+pub unsafe extern "C" fn ksCurrent(ks: *const CKeySet) -> *mut CKey {
+    catch_panic(ptr::null_mut(), || {
+        if ks.is_null() {
+            return ptr::null_mut();
+        }
+
+        unsafe { &*ks }.cursor
+    })
 }
 
+/// # Safety
+///
+/// `ks` has to be NULL or a valid key set.
 #[no_mangle]
-pub extern "C" fn ksAppend(ks: *mut CKeySet, toAppend: *const CKeySet) -> ssize_t {
-    1
+pub unsafe extern "C" fn ksGetCursor(ks: *const CKeySet) -> ssize_t {
+    catch_panic(-1, || {
+        if ks.is_null() || unsafe { &*ks }.cursor.is_null() {
+            return -1;
+        }
+
+        unsafe { &*ks }.current as ssize_t
+    })
 }
 
+/// # Safety
+///
+/// `ks` has to be NULL or a valid key set.
 #[no_mangle]
-pub extern "C" fn ksCut(ks: *mut CKeySet, cutpoint: *const CKey) -> *mut CKeySet {
-    &mut CKeySet::default()
+pub unsafe extern "C" fn ksSetCursor(ks: *mut CKeySet, cursor: ssize_t) -> c_int {
+    catch_panic(-1, || {
+        if ks.is_null() {
+            return -1;
+        }
+
+        let ks = unsafe { &mut *ks };
+
+        if cursor < 0 || cursor as usize >= ks.size {
+            ks.rewind();
+            return 0;
+        }
+
+        ks.set_cursor(cursor as usize);
+
+        1
+    })
 }
 
+/// # Safety
+///
+/// `ks` has to be NULL or a valid key set.
 #[no_mangle]
-pub extern "C" fn ksPop(ks: *mut CKeySet) -> *mut CKey {
-    &mut CKey::default()
+pub unsafe extern "C" fn ksAtCursor(ks: *const CKeySet, position: ssize_t) -> *mut CKey {
+    catch_panic(ptr::null_mut(), || {
+        if ks.is_null() || position < 0 {
+            return ptr::null_mut();
+        }
+
+        match unsafe { &*ks }.keys().get(position as usize) {
+            Some(&key) => key as *mut CKey,
+            None => ptr::null_mut(),
+        }
+    })
 }
 
+/// # Safety
+///
+/// `ks` has to be NULL or a valid key set and `k` NULL or a valid key.
 #[no_mangle]
-pub extern "C" fn ksLookup(ks: *mut CKeySet, k: *mut CKey, options: elektraLookupFlags) -> *mut CKey {
-    &mut CKey::default()
+pub unsafe extern "C" fn ksLookup(ks: *mut CKeySet, k: *mut CKey, options: elektraLookupFlags) -> *mut CKey {
+    catch_panic(ptr::null_mut(), || {
+        if ks.is_null() || k.is_null() {
+            return ptr::null_mut();
+        }
+
+        let options = KeyLookupFlags::from_bits_truncate(options as u32);
+
+        let found = lookup(ks, unsafe { &*k }.key(), options);
+
+        if options.contains(KeyLookupFlags::KDB_O_DEL) {
+            keyDel(k);
+        }
+
+        found
+    })
 }
 
+/// # Safety
+///
+/// `ks` has to be NULL or a valid key set and `name` NULL or a NUL-terminated
+/// string.
 #[no_mangle]
-pub extern "C" fn ksLookupByName(
+pub unsafe extern "C" fn ksLookupByName(
     ks: *mut CKeySet,
     name: *const c_char,
     options: elektraLookupFlags,
 ) -> *mut CKey {
-    &mut CKey::default()
+    catch_panic(ptr::null_mut(), || {
+        if ks.is_null() || name.is_null() {
+            return ptr::null_mut();
+        }
+
+        let key = match KeyName::from_bytes(unsafe { CStr::from_ptr(name) }.to_bytes()) {
+            Ok(x) => Key::new(x),
+            Err(_) => return ptr::null_mut(),
+        };
+
+        let options = KeyLookupFlags::from_bits_truncate(options as u32) - KeyLookupFlags::KDB_O_DEL;
+
+        lookup(ks, &key, options)
+    })
+}
+
+/// # Safety
+///
+/// `contract` has to be NULL or a valid key set and `errorKey` NULL or a
+/// valid key.
+#[no_mangle]
+pub unsafe extern "C" fn kdbOpen(contract: *const CKeySet, errorKey: *mut CKey) -> *mut Kdb {
+    catch_panic(ptr::null_mut(), || {
+        if errorKey.is_null() {
+            return ptr::null_mut();
+        }
+
+        let contract = match unsafe { contract.as_ref() } {
+            Some(contract) => KeySet::from(contract),
+            None => KeySet::default(),
+        };
+
+        plugin::set_loader(loader::load);
+
+        match unsafe { &mut *errorKey }.update(|key| Kdb::open(&contract, key)) {
+            Ok(kdb) => Box::into_raw(Box::new(kdb)),
+            Err(_) => ptr::null_mut(),
+        }
+    })
+}
+
+/// # Safety
+///
+/// `handle` has to be NULL or a handle from `kdbOpen` that is not closed yet
+/// and `errorKey` NULL or a valid key.
+#[no_mangle]
+pub unsafe extern "C" fn kdbClose(handle: *mut Kdb, errorKey: *mut CKey) -> c_int {
+    catch_panic(-1, || {
+        if handle.is_null() {
+            return -1;
+        }
+
+        let kdb = unsafe { Box::from_raw(handle) };
+
+        let result = match unsafe { errorKey.as_mut() } {
+            Some(error_key) => error_key.update(|key| kdb.close(key)),
+            None => kdb.close(&mut Key::new(KeyName::new(KeyNamespace::Cascading, Vec::new()))),
+        };
+
+        match result {
+            Ok(_) => 0,
+            Err(_) => -1,
+        }
+    })
+}
+
+/// # Safety
+///
+/// `handle` has to be NULL or a handle from `kdbOpen` that is not closed yet,
+/// `returned` NULL or a valid key set and `parentKey` NULL or a valid key.
+#[no_mangle]
+pub unsafe extern "C" fn kdbGet(handle: *mut Kdb, returned: *mut CKeySet, parentKey: *mut CKey) -> c_int {
+    catch_panic(-1, || {
+        if handle.is_null() || returned.is_null() || parentKey.is_null() {
+            return -1;
+        }
+
+        let kdb = unsafe { &mut *handle };
+        let parent = unsafe { &mut *parentKey };
+        let mut fetched = KeySet::default();
+
+        match parent.update(|key| kdb.get(&mut fetched, key)) {
+            Ok(true) => (),
+            Ok(false) => return 0,
+            Err(_) => return -1,
+        }
+
+        let parent_name = parent.key().key_name().clone();
+        update_keys(returned, parent, &fetched, Some(&parent_name))
+    })
+}
+
+/// # Safety
+///
+/// `handle` has to be NULL or a handle from `kdbOpen` that is not closed yet,
+/// `returned` NULL or a valid key set and `parentKey` NULL or a valid key.
+#[no_mangle]
+pub unsafe extern "C" fn kdbSet(handle: *mut Kdb, returned: *mut CKeySet, parentKey: *mut CKey) -> c_int {
+    catch_panic(-1, || {
+        if handle.is_null() || returned.is_null() || parentKey.is_null() {
+            return -1;
+        }
+
+        let kdb = unsafe { &mut *handle };
+        let parent = unsafe { &mut *parentKey };
+        let mut ks = KeySet::from(unsafe { &*returned });
+
+        match parent.update(|key| kdb.set(&mut ks, key)) {
+            Ok(true) => update_keys(returned, parent, &ks, None),
+            Ok(false) => 0,
+            Err(_) => -1,
+        }
+    })
+}
+
+/// Brings the keys of `returned` in line with `ks`, see
+/// [`CKeySet::update_keys`]. Returns 1, or -1 with the error set on `parent`
+/// if a key is locked.
+fn update_keys(returned: *mut CKeySet, parent: &mut CKey, ks: &KeySet, below: Option<&KeyName>) -> c_int {
+    match unsafe { &mut *returned }.update_keys(ks, below) {
+        Ok(()) => 1,
+        Err(name) => {
+            let error = KdbError::Interface(format!("the key {} is locked and could not be updated", name));
+            parent.update(|key| error.set_on(key));
+            -1
+        }
+    }
+}
+
+/// # Safety
+///
+/// `pluginName` has to be NULL or a NUL-terminated string, followed by pairs
+/// of a method and a function with the signature of that method, ending with
+/// `ELEKTRA_PLUGIN_END`.
+#[no_mangle]
+pub unsafe extern "C" fn elektraPluginExport(pluginName: *const c_char, mut args: ...) -> *mut CPlugin {
+    catch_panic(ptr::null_mut(), || {
+        if pluginName.is_null() {
+            return ptr::null_mut();
+        }
+
+        let mut plugin = CPlugin::new(unsafe { CStr::from_ptr(pluginName) }.into());
+
+        loop {
+            let method = unsafe { args.next_arg::<plugin_t>() };
+
+            if method == ELEKTRA_PLUGIN_END {
+                break;
+            }
+
+            let function = unsafe { args.next_arg::<*const c_void>() };
+
+            // the callbacks only differ in their arguments, which the caller
+            // guarantees to match the method
+            match method {
+                ELEKTRA_PLUGIN_OPEN => plugin.kdbOpen = unsafe { std::mem::transmute::<*const c_void, kdbOpenPtr>(function) },
+                ELEKTRA_PLUGIN_CLOSE => plugin.kdbClose = unsafe { std::mem::transmute::<*const c_void, kdbClosePtr>(function) },
+                ELEKTRA_PLUGIN_GET => plugin.kdbGet = unsafe { std::mem::transmute::<*const c_void, kdbGetPtr>(function) },
+                ELEKTRA_PLUGIN_SET => plugin.kdbSet = unsafe { std::mem::transmute::<*const c_void, kdbSetPtr>(function) },
+                ELEKTRA_PLUGIN_ERROR => plugin.kdbError = unsafe { std::mem::transmute::<*const c_void, kdbErrorPtr>(function) },
+                ELEKTRA_PLUGIN_COMMIT => plugin.kdbCommit = unsafe { std::mem::transmute::<*const c_void, kdbCommitPtr>(function) },
+                // methods of newer versions of libelektra
+                _ => (),
+            }
+        }
+
+        Box::into_raw(Box::new(plugin))
+    })
+}
+
+/// # Safety
+///
+/// `handle` has to be NULL or a plugin from `elektraPluginExport`.
+#[no_mangle]
+pub unsafe extern "C" fn elektraPluginGetConfig(handle: *mut CPlugin) -> *mut CKeySet {
+    catch_panic(ptr::null_mut(), || {
+        match unsafe { handle.as_ref() } {
+            Some(plugin) => plugin.config,
+            None => ptr::null_mut(),
+        }
+    })
+}
+
+/// # Safety
+///
+/// `plugin` has to be NULL or a plugin from `elektraPluginExport`.
+#[no_mangle]
+pub unsafe extern "C" fn elektraPluginSetData(plugin: *mut CPlugin, data: *mut c_void) {
+    catch_panic((), || {
+        if let Some(plugin) = unsafe { plugin.as_mut() } {
+            plugin.data = data;
+        }
+    })
+}
+
+/// # Safety
+///
+/// `plugin` has to be NULL or a plugin from `elektraPluginExport`.
+#[no_mangle]
+pub unsafe extern "C" fn elektraPluginGetData(plugin: *mut CPlugin) -> *mut c_void {
+    catch_panic(ptr::null_mut(), || {
+        match unsafe { plugin.as_ref() } {
+            Some(plugin) => plugin.data,
+            None => ptr::null_mut(),
+        }
+    })
+}
+
+/// The keys of a `CKeySet` from `start` on, searched in place by
+/// [`KeyLookup::resolve`]. Keys are found by their position in the set.
+struct CKeys<'a> {
+    keys: &'a [*const CKey],
+    start: usize,
+}
+
+impl KeyLookup for CKeys<'_> {
+    type Found = usize;
+
+    fn find(&self, name: &KeyName) -> Option<usize> {
+        self.keys[self.start..]
+            .binary_search_by(|&key| unsafe { &*key }.key().key_name().cmp(name))
+            .ok()
+            .map(|position| self.start + position)
+    }
+
+    fn inspect<T>(&self, found: &usize, f: impl FnOnce(&Key) -> T) -> T {
+        f(unsafe { &*self.keys[*found] }.key())
+    }
+}
+
+/// Resolves `key` on the keys of `ks`, then pops the found key or moves
+/// the cursor to it.
+fn lookup(ks: *mut CKeySet, key: &Key, options: KeyLookupFlags) -> *mut CKey {
+    let ks = unsafe { &mut *ks };
+
+    let start = if options.contains(KeyLookupFlags::KDB_O_NOALL) && !ks.cursor.is_null() {
+        ks.current + 1
+    } else {
+        0
+    };
+
+    let keys = CKeys { keys: ks.keys(), start: start.min(ks.size) };
+
+    let position = match keys.resolve(key, options) {
+        Some(x) => x,
+        None => return ptr::null_mut(),
+    };
+
+    let found = ks.keys()[position] as *mut CKey;
+
+    if !options.contains(KeyLookupFlags::KDB_O_POP) {
+        ks.set_cursor(position);
+        return found;
+    }
+
+    if !ks.is_writable() {
+        return ptr::null_mut();
+    }
+
+    ks.with_keys(|keys| keys.remove(position));
+    unsafe { (*found).ksReference -= 1 };
+    ks.rewind();
+
+    if !write_back(ks) {
+        unsafe { keyDel(found) };
+        return ptr::null_mut();
+    }
+
+    found
+}
+
+/// Writes the key set `ks` back to its key if it is the metadata of a key,
+/// see [`CKeySet::write_back`]. Returns whether the key took the changes,
+/// which the checks of `CKeySet::is_writable` and `CKeySet::accepts` should
+/// ensure, so that the callers fail instead of changing only the key set.
+fn write_back(ks: *const CKeySet) -> bool {
+    unsafe { &*ks }.write_back().is_ok()
 }
@@ -0,0 +1,179 @@
+use std::ffi::{CStr, CString};
+use std::mem;
+use libc::{c_char, c_int, c_void};
+
+use elektra::kdb::KdbError;
+use elektra::key::{Key, KeySet};
+use elektra::plugin::{module_name, Plugin};
+
+use crate::structs::{CKey, CKeySet, CPlugin, kdbGetPtr, kdbOpenPtr};
+use crate::{keyDel, ksDel};
+
+/// The symbol C plugins are exported under by `ELEKTRA_PLUGIN_EXPORT`.
+const PLUGIN_SYMBOL: &[u8] = b"elektraPluginSymbol\0";
+
+type elektraPluginFactory = unsafe extern "C" fn() -> *mut CPlugin;
+
+/// Loads the C plugin `name` from `libelektra-<name>.so`, which `dlopen`
+/// searches for like any other library.
+pub fn load(name: &str) -> Result<Box<dyn Plugin>, KdbError> {
+    let installation = |reason: String| {
+        KdbError::Installation(format!("could not load the plugin {}: {}", name, reason))
+    };
+
+    let library = CString::new(format!("libelektra-{}.so", name))
+        .map_err(|_| installation("the name contains a NUL byte".to_string()))?;
+
+    let handle = unsafe { libc::dlopen(library.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+
+    if handle.is_null() {
+        return Err(installation(dl_error()));
+    }
+
+    let symbol = unsafe { libc::dlsym(handle, PLUGIN_SYMBOL.as_ptr() as *const c_char) };
+
+    if symbol.is_null() {
+        let error = dl_error();
+        unsafe { libc::dlclose(handle) };
+        return Err(installation(error));
+    }
+
+    let factory = unsafe { mem::transmute::<*mut c_void, elektraPluginFactory>(symbol) };
+    let plugin = unsafe { factory() };
+
+    if plugin.is_null() {
+        unsafe { libc::dlclose(handle) };
+        return Err(installation("elektraPluginSymbol returned no plugin".to_string()));
+    }
+
+    Ok(Box::new(CPluginAdapter { handle, plugin }))
+}
+
+fn dl_error() -> String {
+    let error = unsafe { libc::dlerror() };
+
+    if error.is_null() {
+        return "unknown error".to_string();
+    }
+
+    unsafe { CStr::from_ptr(error) }.to_string_lossy().into_owned()
+}
+
+/// A C plugin used through the [`Plugin`] trait.
+///
+/// Every call hands copies of the keys to the plugin as `CKeySet` and
+/// `CKey`, and copies them back once the plugin returns.
+struct CPluginAdapter {
+    handle: *mut c_void,
+    plugin: *mut CPlugin,
+}
+
+impl CPluginAdapter {
+    fn call(&self, function: kdbGetPtr, returned: &mut KeySet, parent: &mut Key) -> Result<bool, KdbError> {
+        let function = match function {
+            Some(x) => x,
+            None => return Ok(false),
+        };
+
+        let ks = Box::into_raw(Box::new(CKeySet::from(returned.clone())));
+        let key = Box::into_raw(Box::new(CKey::from(parent.clone())));
+
+        let result = unsafe { function(self.plugin, ks, key) };
+
+        *parent = unsafe { &*key }.key().clone();
+
+        if result > 0 {
+            *returned = KeySet::from(unsafe { &*ks });
+        }
+
+        unsafe { ksDel(ks) };
+        unsafe { keyDel(key) };
+
+        self.result(result, parent)
+    }
+
+    fn call_with_key(&self, function: kdbOpenPtr, error_key: &mut Key) -> Result<bool, KdbError> {
+        let function = match function {
+            Some(x) => x,
+            None => return Ok(false),
+        };
+
+        let key = Box::into_raw(Box::new(CKey::from(error_key.clone())));
+
+        let result = unsafe { function(self.plugin, key) };
+
+        *error_key = unsafe { &*key }.key().clone();
+        unsafe { keyDel(key) };
+
+        self.result(result, error_key)
+    }
+
+    /// Maps the status a plugin function returned, `-1` for errors.
+    fn result(&self, result: c_int, key: &Key) -> Result<bool, KdbError> {
+        match result {
+            0 => Ok(false),
+            x if x > 0 => Ok(true),
+            _ => Err(KdbError::from_key(key).unwrap_or_else(|| {
+                KdbError::Interface(format!("the plugin {} failed without reporting an error", self.name()))
+            })),
+        }
+    }
+
+    fn c_plugin(&self) -> &CPlugin {
+        unsafe { &*self.plugin }
+    }
+}
+
+impl Plugin for CPluginAdapter {
+    fn name(&self) -> &str {
+        self.c_plugin().name.to_str().unwrap_or_default()
+    }
+
+    /// Asks the plugin for its contract, like libelektra does, with a `get`
+    /// for `system:/elektra/modules/<name>`.
+    fn contract(&self) -> KeySet {
+        let mut contract = KeySet::default();
+        let mut parent = Key::new(module_name(self.name()));
+
+        if self.call(self.c_plugin().kdbGet, &mut contract, &mut parent) != Ok(true) {
+            contract = KeySet::default();
+            contract.append_key(Key::new(module_name(self.name())));
+        }
+
+        contract
+    }
+
+    fn open(&mut self, config: &KeySet, error_key: &mut Key) -> Result<(), KdbError> {
+        unsafe { &mut *self.plugin }.set_config(config.clone());
+
+        self.call_with_key(self.c_plugin().kdbOpen, error_key).map(|_| ())
+    }
+
+    fn get(&mut self, returned: &mut KeySet, parent: &mut Key) -> Result<bool, KdbError> {
+        self.call(self.c_plugin().kdbGet, returned, parent)
+    }
+
+    fn set(&mut self, returned: &mut KeySet, parent: &mut Key) -> Result<bool, KdbError> {
+        self.call(self.c_plugin().kdbSet, returned, parent)
+    }
+
+    fn error(&mut self, returned: &mut KeySet, parent: &mut Key) -> Result<bool, KdbError> {
+        self.call(self.c_plugin().kdbError, returned, parent)
+    }
+
+    fn commit(&mut self, returned: &mut KeySet, parent: &mut Key) -> Result<bool, KdbError> {
+        self.call(self.c_plugin().kdbCommit, returned, parent)
+    }
+
+    fn close(&mut self, error_key: &mut Key) -> Result<(), KdbError> {
+        self.call_with_key(self.c_plugin().kdbClose, error_key).map(|_| ())
+    }
+}
+
+impl Drop for CPluginAdapter {
+    fn drop(&mut self) {
+        // the plugin has to be gone before its code is unloaded
+        drop(unsafe { Box::from_raw(self.plugin) });
+        unsafe { libc::dlclose(self.handle) };
+    }
+}
@@ -2,32 +2,48 @@
 #![allow(non_camel_case_types)]
 #![allow(unused_variables)]
 
-use std::convert::TryFrom;
+use std::cell::OnceCell;
 use std::ffi::{CStr, CString};
 use std::{ptr, slice};
-use std::str::FromStr;
-use libc::{size_t, c_char, c_int, c_uint, c_void};
+use libc::{size_t, c_int, c_uint, c_void};
 
 use bitflags::bitflags;
 
-use elektra_rust::key::{Key, KeyBuilder, KeyError, KeyNamespace, KeySet};
+use elektra::key::{Key, KeyCopyFlags, KeyError, KeyLockFlags, KeyName, KeyNamespace, KeySet, KeyValue};
 use crate::elektraNamespace::{KEY_NS_CASCADING, KEY_NS_DEFAULT, KEY_NS_DIR, KEY_NS_META, KEY_NS_PROC, KEY_NS_SPEC, KEY_NS_SYSTEM, KEY_NS_USER};
 use crate::KEY_NS_NONE;
 
 bitflags! {
+    /// The arguments of `keyNew`, with libelektra's values.
     pub struct KeyNewFlags: i32 {
-        const KEY_NAME = 1;
-        const KEY_VALUE = 1<<1;
+        const KEY_VALUE = 1 << 1;
         const KEY_FLAGS = 3;
+        const KEY_BINARY = 1 << 4;
+        const KEY_SIZE = 1 << 11;
+        const KEY_META = 1 << 15;
+        const KEY_LOCK_NAME = 1 << 17;
+        const KEY_LOCK_VALUE = 1 << 18;
+        const KEY_LOCK_META = 1 << 19;
     }
 }
 
 pub type elektraLockFlags = c_int;
-pub type elektraKeyFlags = c_int;
 pub type elektraKeySetFlags = c_int;
 pub type elektraCopyFlags = c_uint;
 pub type elektraLookupFlags = c_int;
 
+/// The methods of a plugin passed to `elektraPluginExport`, with
+/// libelektra's values.
+pub type plugin_t = c_int;
+
+pub const ELEKTRA_PLUGIN_END: plugin_t = 0;
+pub const ELEKTRA_PLUGIN_OPEN: plugin_t = 1;
+pub const ELEKTRA_PLUGIN_CLOSE: plugin_t = 1 << 1;
+pub const ELEKTRA_PLUGIN_GET: plugin_t = 1 << 2;
+pub const ELEKTRA_PLUGIN_SET: plugin_t = 1 << 3;
+pub const ELEKTRA_PLUGIN_ERROR: plugin_t = 1 << 4;
+pub const ELEKTRA_PLUGIN_COMMIT: plugin_t = 1 << 5;
+
 #[repr(C)]
 pub enum elektraNamespace {
     KEY_NS_NONE=0,
@@ -73,162 +89,220 @@ impl From<elektraNamespace> for KeyNamespace {
     }
 }
 
-#[repr(C)]
-pub union CDataUnion {
-    pub c: *mut c_char,
-    pub v: *mut libc::c_void,
-}
-
-#[repr(C)]
+/// The C representation of a key: an opaque handle to a heap-allocated Rust
+/// `Key`, which the C functions operate on in place.
+///
+/// The C strings handed out for the key, like the name from `keyName` or the
+/// value from `keyValue`, are created on first use and cached here. They stay
+/// valid until the cached part of the key is changed by [`CKey::update`].
+///
+/// The metadata from `keyMeta` is cached as well, but lives as long as the
+/// key: every meta key keeps its C key until it is changed or removed.
 pub struct CKey {
-    pub data: CDataUnion,
-    pub dataSize: size_t,
-
-    pub key: *mut c_char,
-    pub keySize: size_t,
-
-    pub ukey: *mut c_char,
-    pub keyUSize: size_t,
+    key: Key,
+    names: OnceCell<CNames>,
+    value: OnceCell<Option<Vec<u8>>>,
+    meta: OnceCell<*mut CKeySet>,
 
+    /// The number of key sets referencing this key, see `keyIncRef`.
     pub ksReference: size_t,
+}
 
-    pub flags: elektraKeyFlags,
-    pub meta: *mut CKeySet,
+struct CNames {
+    name: CString,
+    unescaped: Vec<u8>,
+    base_name: CString,
 }
 
 impl CKey {
-    pub fn default() -> CKey {
+    pub fn new(key: Key) -> CKey {
         CKey {
-            data: CDataUnion { c: CString::new("qq").expect("qq").into_raw() },
-            dataSize: 0,
-            key: CString::new("qq").expect("qq").into_raw(),
-            keySize: 0,
-            ukey: CString::new("qq").expect("qq").into_raw(),
-            keyUSize: 0,
+            key,
+            names: OnceCell::new(),
+            value: OnceCell::new(),
+            meta: OnceCell::new(),
             ksReference: 0,
-            flags: 0,
-            meta: &mut CKeySet::default(),
         }
     }
 
-    pub fn overwrite(key: *mut CKey, rustKey: Key) {
-        unsafe {
-            let ukeyPtr = (*key).ukey;
-            let dataPtr = (*key).data.c;
-            let dataBinaryPtr = (*key).data.v;
-            let keyPtr = (*key).key;
+    pub fn key(&self) -> &Key {
+        &self.key
+    }
 
-            let c_key: CKey = rustKey.into();
-            std::ptr::write(key, c_key);
+    /// Changes the key with `f` and drops the cached C representations of
+    /// the parts that changed.
+    pub fn update<T, F: FnOnce(&mut Key) -> T>(&mut self, f: F) -> T {
+        let result = f(&mut self.key);
 
-            drop(
-                CString::from_raw(
-                    ukeyPtr
-                )
-            );
+        if self.names.get().is_some_and(|names| names.name.as_bytes() != self.key.key_name().escaped()) {
+            self.names = OnceCell::new();
+        }
 
-            drop(
-                ptr::from_raw(
-                    dataBinaryPtr
-                )
-            );
+        if self.value.get().is_some_and(|value| *value != c_value(&self.key)) {
+            self.value = OnceCell::new();
+        }
 
-            drop(
-                CString::from_raw(
-                    dataPtr
-                )
-            );
+        if let Some(&meta) = self.meta.get() {
+            if !is_meta_of(unsafe { &*meta }, &self.key) {
+                self.sync_meta(unsafe { &mut *meta });
+            }
+        }
 
-            drop(
-                CString::from_raw(
-                    keyPtr
-                )
-            );
-        };
+        result
     }
 
-    pub fn destroy_fields(key: *mut CKey) {
-        unsafe {
-            drop(
-                CString::from_raw(
-                    (*key).ukey
-                )
-            );
+    /// Brings the C meta keys in line with the metadata of the key. C meta
+    /// keys whose Rust meta key has not changed are kept.
+    fn sync_meta(&self, meta: &mut CKeySet) {
+        let old = meta.with_keys(std::mem::take);
+
+        let keys = self.key.meta_keys().values()
+            .map(|(name, rust_meta)| {
+                let rust_meta = rust_meta.borrow();
+                let position = old.binary_search_by(|&c_meta| unsafe { &*c_meta }.key().key_name().cmp(name));
+
+                match position {
+                    Ok(position) if unsafe { &*old[position] }.key().value() == rust_meta.value() => {
+                        unsafe { (*(old[position] as *mut CKey)).ksReference += 1 };
+                        old[position]
+                    }
+                    _ => meta_copy(&rust_meta),
+                }
+            })
+            .collect();
+
+        for c_meta in old {
+            CKey::release(c_meta as *mut CKey);
+        }
 
-            drop(
-                CString::from_raw(
-                    (*key).data.v
-                )
-            );
+        meta.with_keys(|x| *x = keys);
+        meta.rewind();
+    }
 
-            drop(
-                CString::from_raw(
-                    (*key).data.c
-                )
-            );
+    fn names(&self) -> &CNames {
+        self.names.get_or_init(|| {
+            let key_name = self.key.key_name();
 
-            drop(
-                CString::from_raw(
-                    (*key).key
-                )
-            );
-        }
+            CNames {
+                name: CString::new(key_name.escaped()).unwrap_or_default(),
+                unescaped: key_name.unescaped(),
+                base_name: CString::new(key_name.base_name().unwrap_or_default()).unwrap_or_default(),
+            }
+        })
     }
 
-    pub fn destroy(key: *mut CKey) {
-        unsafe {
-            // TODO might need to swap so no accesses to free'd memory is possible
-            Self::destroy_fields(key);
-            Box::from_raw(key);
-        };
+    pub fn name(&self) -> &CStr {
+        &self.names().name
+    }
+
+    pub fn unescaped_name(&self) -> &[u8] {
+        &self.names().unescaped
+    }
+
+    pub fn base_name(&self) -> &CStr {
+        &self.names().base_name
     }
-}
 
-impl Into<CKey> for Key {
-    fn into(self) -> CKey {
-        let name = CString::new(self.name().to_string().clone())
-            .expect("qq")
-            .into_raw();
+    /// The value as C sees it: strings with their terminating NUL, binaries
+    /// as they are and `None` for no value or an empty binary.
+    pub fn value(&self) -> Option<&[u8]> {
+        self.value.get_or_init(|| c_value(&self.key)).as_deref()
+    }
 
-        let mut data = vec![97, 98, 99, 0];
+    /// The metadata of `key` as `CKeySet`, which lives as long as the key.
+    /// Changes to the key set are written back to the key, see
+    /// [`CKeySet::write_back`].
+    pub fn meta(key: *mut CKey) -> *mut CKeySet {
+        let c_key = unsafe { &*key };
 
-        let uKey = CString::new("qq")
-            .expect("qq")
-            .into_raw();
+        *c_key.meta.get_or_init(|| {
+            let keys = c_key.key.meta_keys().values()
+                .map(|(_, rust_meta)| meta_copy(&rust_meta.borrow()))
+                .collect();
 
-        CKey {
-            data: CDataUnion { v: data.as_mut_ptr() as *mut c_void },
-            dataSize: 0,
-            key: name,
-            keySize: 0,
-            ukey: uKey,
-            keyUSize: 0,
-            ksReference: 0,
-            flags: 0,
-            meta: &mut CKeySet::default(),
+            let mut meta = CKeySet::from_keys(keys);
+            meta.owner = key;
+            // keeps ksDel from deleting the key set, see `keyMeta`
+            meta.refs = 1;
+
+            Box::into_raw(Box::new(meta))
+        })
+    }
+
+    /// Drops one key set reference and destroys the key if it was the last.
+    pub fn release(key: *mut CKey) {
+        unsafe {
+            (*key).ksReference -= 1;
+
+            if (*key).ksReference == 0 {
+                CKey::destroy(key);
+            }
         }
     }
+
+    pub fn destroy(key: *mut CKey) {
+        drop(unsafe { Box::from_raw(key) });
+    }
 }
 
-impl TryFrom<&CKey> for Key {
-    type Error = KeyError;
+impl Drop for CKey {
+    fn drop(&mut self) {
+        if let Some(meta) = self.meta.take() {
+            CKeySet::destroy(meta);
+        }
+    }
+}
 
-    fn try_from(value: &CKey) -> Result<Self, Self::Error> {
-        let cstr = unsafe { CStr::from_ptr(value.key) };
+impl From<Key> for CKey {
+    fn from(key: Key) -> CKey {
+        CKey::new(key)
+    }
+}
 
-        let key_name_cstr = cstr.to_str()
-            .expect("key name cannot be cast to string");
+/// Copies a meta key into a new C key, referenced once. Meta keys cannot be
+/// changed, like in libelektra.
+fn meta_copy(rust_meta: &Key) -> *const CKey {
+    let mut c_meta = CKey::new(rust_meta.clone());
+    c_meta.key.lock(META_KEY_LOCKS);
+    c_meta.ksReference = 1;
 
-        let newValue = unsafe {
-            slice::from_raw_parts(value.data.v as *const u8, value.dataSize)
-        };
+    Box::into_raw(Box::new(c_meta))
+}
 
-        KeyBuilder::from_str(key_name_cstr)?
-            .value(newValue.to_vec())
-            .build()
+/// The parts of meta keys that are locked in a key set from `keyMeta`.
+pub const META_KEY_LOCKS: KeyLockFlags = KeyLockFlags::KEY_LOCK_NAME
+    .union(KeyLockFlags::KEY_LOCK_VALUE)
+    .union(KeyLockFlags::KEY_LOCK_META);
+
+fn c_value(key: &Key) -> Option<Vec<u8>> {
+    match key.value() {
+        Some(KeyValue::String(value)) => {
+            let mut data = value.clone();
+            data.push(0);
+            Some(data)
+        }
+        Some(KeyValue::Binary(value)) if !value.is_empty() => Some(value.clone()),
+        _ => None,
     }
 }
 
+/// Whether `meta` still holds the same metadata as `key`.
+fn is_meta_of(meta: &CKeySet, key: &Key) -> bool {
+    meta.size == key.meta_keys().size()
+        && meta.keys().iter().zip(key.meta_keys().values()).all(|(&c_meta, (name, rust_meta))| {
+            let c_meta = unsafe { &*c_meta }.key();
+            c_meta.key_name() == name && c_meta.value() == rust_meta.borrow().value()
+        })
+}
+
+/// Whether `a` and `b` have the same metadata.
+fn same_meta(a: &Key, b: &Key) -> bool {
+    a.meta_keys().size() == b.meta_keys().size()
+        && a.meta_keys().values().zip(b.meta_keys().values()).all(|((a_name, a_meta), (b_name, b_meta))| {
+            a_name == b_name && a_meta.borrow().value() == b_meta.borrow().value()
+        })
+}
+
 #[repr(C)]
 pub struct CKeySet
 {
@@ -244,6 +318,9 @@ pub struct CKeySet
 
     pub refs: u16,
     pub reserved: u16,
+
+    /// The key whose metadata this is, for the key set from `keyMeta`.
+    pub owner: *mut CKey,
 }
 
 impl CKeySet {
@@ -257,20 +334,288 @@ impl CKeySet {
             flags: 0,
             refs: 0,
             reserved: 0,
+            owner: ptr::null_mut(),
+        }
+    }
+
+    /// Stores `keys` in a NULL-terminated array. The keys have to be
+    /// referenced already, see [`CKey::ksReference`].
+    pub fn from_keys(keys: Vec<*const CKey>) -> CKeySet {
+        let mut array = keys;
+        let size = array.len();
+        array.push(ptr::null());
+
+        let mut array = std::mem::ManuallyDrop::new(array);
+
+        CKeySet {
+            array: array.as_mut_ptr(),
+            size,
+            alloc: array.capacity(),
+            ..CKeySet::default()
+        }
+    }
+
+    pub fn keys(&self) -> &[*const CKey] {
+        if self.array.is_null() {
+            return &[];
+        }
+
+        unsafe { slice::from_raw_parts(self.array, self.size) }
+    }
+
+    pub fn rewind(&mut self) {
+        self.cursor = ptr::null_mut();
+        self.current = 0;
+    }
+
+    pub fn set_cursor(&mut self, position: usize) {
+        self.cursor = self.keys()[position] as *mut CKey;
+        self.current = position;
+    }
+
+    /// Gives access to the keys as a `Vec`, without touching the references
+    /// of the keys. The array stays NULL-terminated afterwards.
+    pub fn with_keys<T, F: FnOnce(&mut Vec<*const CKey>) -> T>(&mut self, f: F) -> T {
+        let mut keys = if self.array.is_null() {
+            Vec::new()
+        } else {
+            let mut keys = unsafe { Vec::from_raw_parts(self.array, self.size + 1, self.alloc) };
+            keys.pop();
+            keys
+        };
+
+        let result = f(&mut keys);
+
+        keys.push(ptr::null());
+        let mut keys = std::mem::ManuallyDrop::new(keys);
+
+        self.array = keys.as_mut_ptr();
+        self.size = keys.len() - 1;
+        self.alloc = keys.capacity();
+
+        result
+    }
+
+    /// Binary searches the sorted keys for `name`, like
+    /// [`slice::binary_search`].
+    pub fn search(&self, name: &KeyName) -> Result<usize, usize> {
+        self.keys().binary_search_by(|&key| unsafe { &*key }.key().key_name().cmp(name))
+    }
+
+    /// Whether keys may be added or removed, which is not the case for the
+    /// key set from `keyMeta` while the metadata of its key is locked.
+    pub fn is_writable(&self) -> bool {
+        unsafe { self.owner.as_ref() }
+            .is_none_or(|owner| !owner.key().is_locked(KeyLockFlags::KEY_LOCK_META))
+    }
+
+    /// Whether `key` may be added: the key set from `keyMeta` only takes
+    /// meta keys.
+    pub fn accepts(&self, key: &CKey) -> bool {
+        let name = key.key().key_name();
+        self.is_writable() && (self.owner.is_null() || name.namespace() == KeyNamespace::Meta && !name.is_root())
+    }
+
+    /// Writes the keys of the key set from `keyMeta` back to its key as its
+    /// metadata. Other key sets are left alone.
+    pub fn write_back(&self) -> Result<(), KeyError> {
+        let owner = match unsafe { self.owner.as_mut() } {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+
+        let mut meta = Key::new(KeyName::new(KeyNamespace::Cascading, Vec::new()));
+
+        for &c_meta in self.keys() {
+            let c_meta = unsafe { &*c_meta }.key();
+
+            let value = match c_meta.value() {
+                Some(KeyValue::String(value)) | Some(KeyValue::Binary(value)) => value.as_slice(),
+                None => &[],
+            };
+
+            meta.set_meta(c_meta.key_name().escaped(), value)?;
+        }
+
+        owner.update(|key| key.copy_from(&meta, KeyCopyFlags::KEY_CP_META))
+    }
+
+    /// Makes the keys below `parent`, or all keys without `parent`, those of
+    /// `ks`. Keys that are in the key set already are changed in place, so
+    /// that pointers to them stay valid, only new keys are added.
+    ///
+    /// Fails with the name of the last key whose value or metadata could not
+    /// be changed, as it is locked. The other keys are updated nonetheless.
+    pub fn update_keys(&mut self, ks: &KeySet, parent: Option<&KeyName>) -> Result<(), KeyName> {
+        let replaced = self.keys()
+            .iter()
+            .map(|&key| Key::new(unsafe { &*key }.key().key_name().clone()))
+            .collect::<KeySet>();
+        let replaced = match parent {
+            Some(parent) => replaced.below(parent),
+            None => replaced,
+        };
+
+        let mut result = Ok(());
+
+        self.with_keys(|keys| {
+            keys.retain(|&key| {
+                let name = unsafe { &*key }.key().key_name();
+                let removed = replaced.contains(name) && !ks.contains(name);
+
+                if removed {
+                    CKey::release(key as *mut CKey);
+                }
+
+                !removed
+            });
+
+            for (name, key) in ks.values() {
+                let key = key.borrow();
+
+                match keys.binary_search_by(|&c_key| unsafe { &*c_key }.key().key_name().cmp(name)) {
+                    Ok(position) => {
+                        let c_key = unsafe { &mut *(keys[position] as *mut CKey) };
+                        let mut flags = KeyCopyFlags::empty();
+
+                        if c_key.key().value() != key.value() {
+                            flags |= KeyCopyFlags::KEY_CP_VALUE;
+                        }
+
+                        if !same_meta(c_key.key(), &key) {
+                            flags |= KeyCopyFlags::KEY_CP_META;
+                        }
+
+                        if !flags.is_empty() && c_key.update(|c_key| c_key.copy_from(&key, flags)).is_err() {
+                            result = Err(name.clone());
+                        }
+                    }
+                    Err(position) => {
+                        let mut c_key = CKey::new(key.clone());
+                        c_key.ksReference = 1;
+                        c_key.key.lock(KeyLockFlags::KEY_LOCK_NAME);
+
+                        keys.insert(position, Box::into_raw(Box::new(c_key)) as *const CKey);
+                    }
+                }
+            }
+        });
+
+        self.rewind();
+
+        result
+    }
+
+    /// Drops the references to all keys and empties the key set.
+    pub fn clear(&mut self) {
+        let keys = self.with_keys(std::mem::take);
+
+        for key in keys {
+            CKey::release(key as *mut CKey);
+        }
+
+        self.rewind();
+    }
+
+    pub fn destroy(ks: *mut CKeySet) {
+        drop(unsafe { Box::from_raw(ks) });
+    }
+}
+
+impl Drop for CKeySet {
+    fn drop(&mut self) {
+        for &key in self.keys() {
+            CKey::release(key as *mut CKey);
+        }
+
+        if !self.array.is_null() {
+            drop(
+                unsafe { Vec::from_raw_parts(self.array, self.size + 1, self.alloc) }
+            );
         }
     }
 }
 
-impl Into<CKeySet> for KeySet {
-    fn into(self) -> CKeySet {
-        todo!()
+impl From<KeySet> for CKeySet {
+    fn from(ks: KeySet) -> CKeySet {
+        CKeySet::from_keys(referenced_copies(&ks))
+    }
+}
+
+/// Copies the keys of `ks` into new C keys, referenced once each.
+fn referenced_copies(ks: &KeySet) -> Vec<*const CKey> {
+    ks.values()
+        .map(|(_, key)| {
+            let mut c_key = CKey::new(key.borrow().clone());
+            c_key.ksReference = 1;
+
+            Box::into_raw(Box::new(c_key)) as *const CKey
+        })
+        .collect()
+}
+
+impl From<&CKeySet> for KeySet {
+    fn from(value: &CKeySet) -> Self {
+        value.keys()
+            .iter()
+            .map(|&key| unsafe { &*key }.key().clone())
+            .collect()
     }
 }
 
-impl TryFrom<&CKeySet> for KeySet {
-    type Error = KeyError;
+pub type kdbOpenPtr = Option<unsafe extern "C" fn(*mut CPlugin, *mut CKey) -> c_int>;
+pub type kdbClosePtr = kdbOpenPtr;
+pub type kdbGetPtr = Option<unsafe extern "C" fn(*mut CPlugin, *mut CKeySet, *mut CKey) -> c_int>;
+pub type kdbSetPtr = kdbGetPtr;
+pub type kdbErrorPtr = kdbGetPtr;
+pub type kdbCommitPtr = kdbGetPtr;
+
+/// The C representation of a plugin, as created by `elektraPluginExport`.
+///
+/// C plugins only use it through functions like `elektraPluginGetData`, so
+/// its layout does not have to match the one of libelektra.
+pub struct CPlugin {
+    pub name: CString,
+    pub config: *mut CKeySet,
+    pub data: *mut c_void,
+
+    pub kdbOpen: kdbOpenPtr,
+    pub kdbClose: kdbClosePtr,
+    pub kdbGet: kdbGetPtr,
+    pub kdbSet: kdbSetPtr,
+    pub kdbError: kdbErrorPtr,
+    pub kdbCommit: kdbCommitPtr,
+}
 
-    fn try_from(value: &CKeySet) -> Result<Self, Self::Error> {
-        todo!()
+impl CPlugin {
+    pub fn new(name: CString) -> CPlugin {
+        CPlugin {
+            name,
+            config: ptr::null_mut(),
+            data: ptr::null_mut(),
+            kdbOpen: None,
+            kdbClose: None,
+            kdbGet: None,
+            kdbSet: None,
+            kdbError: None,
+            kdbCommit: None,
+        }
     }
-}
\ No newline at end of file
+
+    /// Replaces the configuration returned by `elektraPluginGetConfig`.
+    pub fn set_config(&mut self, config: KeySet) {
+        if !self.config.is_null() {
+            CKeySet::destroy(self.config);
+        }
+
+        self.config = Box::into_raw(Box::new(CKeySet::from(config)));
+    }
+}
+
+impl Drop for CPlugin {
+    fn drop(&mut self) {
+        if !self.config.is_null() {
+            CKeySet::destroy(self.config);
+        }
+    }
+}
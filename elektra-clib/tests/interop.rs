@@ -0,0 +1,148 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The directory with the library built for these tests.
+fn library_directory() -> PathBuf {
+    let executable = env::current_exe().unwrap();
+    executable.parent().unwrap().to_path_buf()
+}
+
+fn compile(source: &str, output: &Path, arguments: &[&str]) {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/interop");
+
+    let status = Command::new(env::var("CC").unwrap_or_else(|_| "cc".to_string()))
+        .args(arguments)
+        .arg("-I")
+        .arg(&directory)
+        .arg("-o")
+        .arg(output)
+        .arg(directory.join(source))
+        .arg("-L")
+        .arg(library_directory())
+        .arg("-lelektra_rust")
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+}
+
+/// Compiles and runs one of the C programs checking the functions of the
+/// library, which exits with the number of failed checks.
+fn run_checks(source: &str) {
+    let executable = Path::new(env!("CARGO_TARGET_TMPDIR")).join(Path::new(source).file_stem().unwrap());
+
+    compile(source, &executable, &[]);
+
+    let output = Command::new(&executable)
+        .env("LD_LIBRARY_PATH", library_directory())
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}{}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+}
+
+/// The `lines` plugin, either the one written in C or the example written
+/// in Rust.
+enum Lines {
+    C,
+    Rust,
+}
+
+struct Host {
+    directory: PathBuf,
+}
+
+impl Host {
+    fn new(name: &str, lines: Lines) -> Host {
+        let directory = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+
+        let plugin = directory.join("libelektra-lines.so");
+
+        match lines {
+            Lines::C => compile("plugin.c", &plugin, &["-shared", "-fPIC"]),
+            Lines::Rust => {
+                let example = library_directory().parent().unwrap().join("examples/liblines.so");
+                fs::copy(example, plugin).unwrap();
+            }
+        }
+
+        compile("host.c", &directory.join("host"), &[]);
+
+        Host { directory }
+    }
+
+    fn run(&self, arguments: &[&str]) -> String {
+        let library_path = env::join_paths([self.directory.clone(), library_directory()]).unwrap();
+
+        let output = Command::new(self.directory.join("host"))
+            .arg(self.directory.join("bootstrap.ecf"))
+            .args(arguments)
+            .env("LD_LIBRARY_PATH", library_path)
+            .output()
+            .unwrap();
+
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+        String::from_utf8(output.stdout).unwrap()
+    }
+}
+
+fn check_lines(host: &Host) {
+    assert_eq!(host.run(&["mount", "user:\\/app", "lines", " = "]), "1 \n");
+
+    assert_eq!(host.run(&["set", "user:/app/port", "8080"]), "1 \n");
+    assert_eq!(fs::read_to_string(host.directory.join("app.lines")).unwrap(), "user:/app/port = 8080\n");
+
+    assert_eq!(host.run(&["get", "user:/app/port"]), "8080\n1 \n");
+    assert_eq!(host.run(&["reget", "user:/app/port"]), "same 8080\n1 \n");
+
+    fs::write(host.directory.join("app.lines"), "user:/app/port\n").unwrap();
+    assert_eq!(host.run(&["get", "user:/app/port"]), "-1 C03100\n");
+}
+
+#[test]
+fn test_c_plugin() {
+    check_lines(&Host::new("c-plugin", Lines::C));
+}
+
+#[test]
+fn test_rust_plugin() {
+    check_lines(&Host::new("rust-plugin", Lines::Rust));
+}
+
+#[test]
+fn test_missing_c_plugin() {
+    let host = Host::new("missing-c-plugin", Lines::C);
+
+    assert_eq!(host.run(&["mount", "user:\\/app", "missing", "="]), "1 \n");
+    assert_eq!(host.run(&["get", "user:/app/port"]), "-1 C01200\n");
+}
+
+#[test]
+fn test_key_set_cursor() {
+    run_checks("cursor.c");
+}
+
+#[test]
+fn test_key_set() {
+    run_checks("keyset.c");
+}
+
+#[test]
+fn test_key_new() {
+    run_checks("keynew.c");
+}
+
+#[test]
+fn test_lookup() {
+    run_checks("lookup.c");
+}
+
+#[test]
+fn test_meta() {
+    run_checks("meta.c");
+}
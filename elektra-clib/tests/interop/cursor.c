@@ -0,0 +1,91 @@
+// Checks the internal cursor of key sets.
+
+#include "kdb.h"
+#include "tests.h"
+
+static void test_next (void)
+{
+	KeySet * ks = ksNew (3, keyNew ("user:/a", KEY_END), keyNew ("user:/b", KEY_END), keyNew ("user:/c", KEY_END), KS_END);
+
+	succeed_if (ksCurrent (ks) == 0, "ksNew should rewind the key set");
+	succeed_if (ksGetCursor (ks) == -1, "a rewound key set should have no cursor");
+
+	succeed_if_same_string (keyName (ksNext (ks)), "user:/a");
+	succeed_if (ksGetCursor (ks) == 0, "the cursor should be on the first key");
+	succeed_if_same_string (keyName (ksNext (ks)), "user:/b");
+	succeed_if_same_string (keyName (ksCurrent (ks)), "user:/b");
+	succeed_if_same_string (keyName (ksNext (ks)), "user:/c");
+	succeed_if (ksNext (ks) == 0, "ksNext should stop after the last key");
+	succeed_if (ksCurrent (ks) == 0, "there should be no current key after the last key");
+
+	ksRewind (ks);
+	succeed_if_same_string (keyName (ksNext (ks)), "user:/a");
+
+	ksDel (ks);
+}
+
+static void test_set_cursor (void)
+{
+	KeySet * ks = ksNew (3, keyNew ("user:/a", KEY_END), keyNew ("user:/b", KEY_END), keyNew ("user:/c", KEY_END), KS_END);
+
+	succeed_if_same_string (keyName (ksAtCursor (ks, 1)), "user:/b");
+	succeed_if (ksAtCursor (ks, 3) == 0, "ksAtCursor should return NULL after the last key");
+	succeed_if (ksAtCursor (ks, -1) == 0, "ksAtCursor should return NULL for negative positions");
+	succeed_if (ksCurrent (ks) == 0, "ksAtCursor should not move the cursor");
+
+	succeed_if (ksSetCursor (ks, 2) == 1, "ksSetCursor should accept positions in the key set");
+	succeed_if_same_string (keyName (ksCurrent (ks)), "user:/c");
+	succeed_if (ksGetCursor (ks) == 2, "ksGetCursor should return the position set");
+
+	succeed_if (ksSetCursor (ks, 3) == 0, "ksSetCursor should reject positions after the last key");
+	succeed_if (ksCurrent (ks) == 0, "an invalid position should rewind the key set");
+
+	ksDel (ks);
+}
+
+static void test_cursor_after_cut (void)
+{
+	KeySet * ks = ksNew (4, keyNew ("user:/a", KEY_END), keyNew ("user:/b", KEY_END), keyNew ("user:/b/c", KEY_END),
+			     keyNew ("user:/d", KEY_END), KS_END);
+	Key * cutpoint = keyNew ("user:/b", KEY_END);
+
+	// a cursor on a cut key moves to the key before the cut keys
+	ksSetCursor (ks, 2);
+	KeySet * cut = ksCut (ks, cutpoint);
+
+	succeed_if_same_string (keyName (ksCurrent (ks)), "user:/a");
+	succeed_if_same_string (keyName (ksNext (ks)), "user:/d");
+	succeed_if_same_string (keyName (ksAtCursor (ks, 1)), "user:/d");
+	succeed_if (ksAtCursor (ks, 2) == 0, "the cut keys should be gone");
+
+	succeed_if (ksGetSize (cut) == 2, "the cut keys should be returned");
+	succeed_if (ksCurrent (cut) == 0, "the cut keys should have no cursor");
+	succeed_if_same_string (keyName (ksNext (cut)), "user:/b");
+	succeed_if_same_string (keyName (ksAtCursor (cut, 1)), "user:/b/c");
+
+	// a cursor after the cut keys stays on its key
+	ksAppend (ks, cut);
+	ksSetCursor (ks, 3);
+	ksDel (ksCut (ks, cutpoint));
+	succeed_if_same_string (keyName (ksCurrent (ks)), "user:/d");
+	succeed_if (ksGetCursor (ks) == 1, "the cursor should move with its key");
+
+	// without keys before the cut keys, the key set is rewound
+	ksDel (cut);
+	cut = ksCut (ks, keyNew ("user:/", KEY_END));
+	succeed_if (ksCurrent (ks) == 0, "a key set without keys before the cut keys should be rewound");
+	succeed_if (ksGetSize (ks) == 0, "all keys should be cut");
+
+	ksDel (cut);
+	keyDel (cutpoint);
+	ksDel (ks);
+}
+
+int main (void)
+{
+	test_next ();
+	test_set_cursor ();
+	test_cursor_after_cut ();
+
+	return nbError;
+}
@@ -0,0 +1,89 @@
+// Runs one kdb operation on the bootstrap file in argv[1] and prints the result:
+//
+//   host <bootstrap> mount <mountpoint> <plugin> <separator>
+//   host <bootstrap> set <name> <value>
+//   host <bootstrap> get <name>
+//   host <bootstrap> reget <name>
+//
+// reget gets the keys again with a second handle and prints whether the key
+// kept its pointer.
+
+#include <stdio.h>
+#include <string.h>
+
+#include "kdb.h"
+
+static void mountpointKey (KeySet * ks, const char * mountpoint, const char * setting, const char * value)
+{
+	char name[256];
+	snprintf (name, sizeof name, "system:/elektra/mountpoints/%s/%s", mountpoint, setting);
+	ksAppendKey (ks, keyNew (name, KEY_VALUE, value, KEY_END));
+}
+
+static void printError (int result, Key * parentKey)
+{
+	const Key * number = keyGetMeta (parentKey, "error/number");
+	printf ("%d %s\n", result, number ? keyString (number) : "");
+}
+
+int main (int argc, char ** argv)
+{
+	KeySet * contract = ksNew (1, keyNew ("system:/elektra/contract/kdb/storage", KEY_VALUE, argv[1], KEY_END), KS_END);
+	Key * parentKey = keyNew (!strcmp (argv[2], "mount") ? "system:/elektra/mountpoints" : "user:/app", KEY_END);
+	KeySet * ks = ksNew (0, KS_END);
+
+	KDB * kdb = kdbOpen (contract, parentKey);
+	if (!kdb)
+	{
+		printError (-1, parentKey);
+		return 0;
+	}
+
+	int result = kdbGet (kdb, ks, parentKey);
+
+	if (result >= 0 && !strcmp (argv[2], "mount"))
+	{
+		mountpointKey (ks, argv[3], "path", "app.lines");
+		mountpointKey (ks, argv[3], "plugin", argv[4]);
+		mountpointKey (ks, argv[3], "config/separator", argv[5]);
+		result = kdbSet (kdb, ks, parentKey);
+	}
+	else if (result >= 0 && !strcmp (argv[2], "set"))
+	{
+		ksAppendKey (ks, keyNew (argv[3], KEY_VALUE, argv[4], KEY_END));
+		result = kdbSet (kdb, ks, parentKey);
+	}
+	else if (result >= 0 && !strcmp (argv[2], "get"))
+	{
+		Key * key = ksLookupByName (ks, argv[3], 0);
+		printf ("%s\n", key ? keyString (key) : "(none)");
+	}
+	else if (result >= 0 && !strcmp (argv[2], "reget"))
+	{
+		// the reference keeps a replaced key from being freed, so that its
+		// address is not reused
+		Key * key = ksLookupByName (ks, argv[3], 0);
+		keyIncRef (key);
+		KDB * other = kdbOpen (contract, parentKey);
+		result = other ? kdbGet (other, ks, parentKey) : -1;
+
+		Key * found = ksLookupByName (ks, argv[3], 0);
+		printf ("%s %s\n", key && found == key ? "same" : "new", found ? keyString (found) : "(none)");
+		keyDecRef (key);
+		keyDel (key);
+
+		if (other)
+		{
+			kdbClose (other, parentKey);
+		}
+	}
+
+	printError (result, parentKey);
+
+	kdbClose (kdb, parentKey);
+	ksDel (ks);
+	ksDel (contract);
+	keyDel (parentKey);
+
+	return 0;
+}
@@ -0,0 +1,92 @@
+// The declarations of libelektra's kdb.h that the interop tests use, with
+// libelektra's values.
+
+#ifndef KDB_H
+#define KDB_H
+
+#include <sys/types.h>
+
+typedef struct _Key Key;
+typedef struct _KeySet KeySet;
+typedef struct _KDB KDB;
+typedef ssize_t elektraCursor;
+
+enum
+{
+	KEY_VALUE = 1 << 1,
+	KEY_FLAGS = 3,
+	KEY_BINARY = 1 << 4,
+	KEY_SIZE = 1 << 11,
+	KEY_META = 1 << 15,
+	KEY_LOCK_NAME = 1 << 17,
+	KEY_LOCK_VALUE = 1 << 18,
+	KEY_LOCK_META = 1 << 19,
+};
+
+#define KEY_END 0
+#define KS_END ((Key *) 0)
+
+enum
+{
+	KDB_O_NONE = 0,
+	KDB_O_DEL = 1,
+	KDB_O_POP = 1 << 1,
+	KDB_O_NOALL = 1 << 14,
+	KDB_O_SPEC = 1 << 15,
+	KDB_O_NOCASCADING = 1 << 17,
+	KDB_O_NOSPEC = 1 << 18,
+	KDB_O_NODEFAULT = 1 << 19,
+};
+
+Key * keyNew (const char * name, ...);
+int keyDel (Key * key);
+ssize_t keyIncRef (Key * key);
+ssize_t keyDecRef (Key * key);
+ssize_t keyGetRef (const Key * key);
+int keyLock (Key * key, int what);
+int keyIsLocked (const Key * key, int what);
+
+const char * keyName (const Key * key);
+ssize_t keySetName (Key * key, const char * newname);
+const char * keyBaseName (const Key * key);
+
+const void * keyValue (const Key * key);
+ssize_t keyGetValueSize (const Key * key);
+const char * keyString (const Key * key);
+ssize_t keySetString (Key * key, const char * newString);
+ssize_t keyGetBinary (const Key * key, void * returnedBinary, size_t maxSize);
+int keyIsBinary (const Key * key);
+
+const Key * keyGetMeta (const Key * key, const char * metaName);
+ssize_t keySetMeta (Key * key, const char * metaName, const char * newMetaString);
+KeySet * keyMeta (Key * key);
+int keyCopyMeta (Key * dest, const Key * source, const char * metaName);
+
+KeySet * ksNew (size_t alloc, ...);
+KeySet * ksDup (const KeySet * source);
+int ksCopy (KeySet * dest, const KeySet * source);
+int ksClear (KeySet * ks);
+int ksDel (KeySet * ks);
+ssize_t ksGetSize (const KeySet * ks);
+
+ssize_t ksAppendKey (KeySet * ks, Key * toAppend);
+ssize_t ksAppend (KeySet * ks, const KeySet * toAppend);
+KeySet * ksCut (KeySet * ks, const Key * cutpoint);
+Key * ksPop (KeySet * ks);
+
+int ksRewind (KeySet * ks);
+Key * ksNext (KeySet * ks);
+Key * ksCurrent (const KeySet * ks);
+elektraCursor ksGetCursor (const KeySet * ks);
+int ksSetCursor (KeySet * ks, elektraCursor cursor);
+Key * ksAtCursor (const KeySet * ks, elektraCursor cursor);
+
+Key * ksLookup (KeySet * ks, Key * key, int options);
+Key * ksLookupByName (KeySet * ks, const char * name, int options);
+
+KDB * kdbOpen (const KeySet * contract, Key * errorKey);
+int kdbClose (KDB * handle, Key * errorKey);
+int kdbGet (KDB * handle, KeySet * returned, Key * parentKey);
+int kdbSet (KDB * handle, KeySet * returned, Key * parentKey);
+
+#endif
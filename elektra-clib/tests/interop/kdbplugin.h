@@ -0,0 +1,29 @@
+// The declarations of libelektra's kdbplugin.h that the interop tests use,
+// with libelektra's values, for plugins built as shared libraries.
+
+#ifndef KDBPLUGIN_H
+#define KDBPLUGIN_H
+
+#include "kdb.h"
+
+typedef struct _Plugin Plugin;
+
+typedef enum
+{
+	ELEKTRA_PLUGIN_OPEN = 1,
+	ELEKTRA_PLUGIN_CLOSE = 1 << 1,
+	ELEKTRA_PLUGIN_GET = 1 << 2,
+	ELEKTRA_PLUGIN_SET = 1 << 3,
+	ELEKTRA_PLUGIN_ERROR = 1 << 4,
+	ELEKTRA_PLUGIN_COMMIT = 1 << 5,
+	ELEKTRA_PLUGIN_END = 0
+} plugin_t;
+
+#define ELEKTRA_PLUGIN_EXPORT elektraPluginSymbol (void)
+
+Plugin * elektraPluginExport (const char * pluginName, ...);
+KeySet * elektraPluginGetConfig (Plugin * handle);
+void elektraPluginSetData (Plugin * plugin, void * data);
+void * elektraPluginGetData (Plugin * plugin);
+
+#endif
@@ -0,0 +1,88 @@
+// Checks the arguments of keyNew.
+
+#include "kdb.h"
+#include "tests.h"
+
+static void test_name_and_value (void)
+{
+	Key * key = keyNew ("user:/a", KEY_END);
+	succeed_if_same_string (keyName (key), "user:/a");
+	succeed_if_same_string (keyString (key), "");
+	succeed_if (keyGetValueSize (key) == 1, "a key without a value should have the size of an empty string");
+	succeed_if (!keyIsBinary (key), "a new key should not be binary");
+	keyDel (key);
+
+	key = keyNew ("system:/a/../b", KEY_VALUE, "value", KEY_END);
+	succeed_if_same_string (keyName (key), "system:/b");
+	succeed_if_same_string (keyString (key), "value");
+	succeed_if (keyGetValueSize (key) == 6, "the size of a string should count its NUL");
+	keyDel (key);
+
+	succeed_if (keyNew ("invalid", KEY_END) == 0, "keyNew should reject invalid names");
+	succeed_if (keyNew (0, KEY_END) == 0, "keyNew should reject NULL");
+}
+
+static void test_meta (void)
+{
+	Key * key = keyNew ("user:/a", KEY_META, "type", "long", KEY_META, "meta:/check/range", "0-10", KEY_VALUE, "5", KEY_END);
+
+	succeed_if_same_string (keyString (key), "5");
+	succeed_if_same_string (keyString (keyGetMeta (key, "type")), "long");
+	succeed_if_same_string (keyString (keyGetMeta (key, "check/range")), "0-10");
+	succeed_if (ksGetSize (keyMeta (key)) == 2, "keyNew should add every meta key");
+	keyDel (key);
+
+	succeed_if (keyNew ("user:/a", KEY_META, "user:/type", "long", KEY_END) == 0, "keyNew should reject invalid meta names");
+}
+
+static void test_binary (void)
+{
+	int value = 42;
+	Key * key = keyNew ("user:/a", KEY_BINARY, KEY_SIZE, sizeof (value), KEY_VALUE, &value, KEY_END);
+	int read = 0;
+
+	succeed_if (keyIsBinary (key), "KEY_BINARY should make the key binary");
+	succeed_if (keyGetValueSize (key) == sizeof (value), "KEY_SIZE should give the size of the value");
+	succeed_if (keyGetBinary (key, &read, sizeof (read)) == sizeof (read), "the value should be read");
+	succeed_if (read == 42, "the value should be copied");
+	succeed_if (keyValue (key) != &value, "the value should be copied");
+	keyDel (key);
+
+	// without KEY_SIZE, the value is read up to and including its NUL
+	key = keyNew ("user:/a", KEY_BINARY, KEY_VALUE, "abc", KEY_END);
+	succeed_if (keyGetValueSize (key) == 4, "a binary value without KEY_SIZE should end after its NUL");
+	keyDel (key);
+
+	key = keyNew ("user:/a", KEY_BINARY, KEY_END);
+	succeed_if (keyIsBinary (key), "a binary key without a value should be binary");
+	succeed_if (keyGetValueSize (key) == 0, "a binary key without a value should have no value");
+	succeed_if (keyValue (key) == 0, "a binary key without a value should have no value");
+	keyDel (key);
+}
+
+static void test_flags (void)
+{
+	Key * key = keyNew ("user:/a", KEY_FLAGS, KEY_BINARY | KEY_LOCK_VALUE, KEY_END);
+	succeed_if (keyIsBinary (key), "KEY_FLAGS should take KEY_BINARY");
+	succeed_if (keyIsLocked (key, KEY_LOCK_VALUE), "KEY_FLAGS should take the locks");
+	succeed_if (!keyIsLocked (key, KEY_LOCK_NAME), "KEY_FLAGS should only lock what is given");
+	keyDel (key);
+
+	key = keyNew ("user:/a", KEY_VALUE, "x", KEY_LOCK_NAME, KEY_LOCK_META, KEY_END);
+	succeed_if (keyIsLocked (key, KEY_LOCK_NAME), "locks should be taken as arguments");
+	succeed_if (keyIsLocked (key, KEY_LOCK_META), "locks should be taken as arguments");
+	succeed_if (keySetName (key, "user:/b") == -1, "the name should be locked");
+	succeed_if (keySetMeta (key, "type", "string") == -1, "the metadata should be locked");
+	succeed_if (keySetString (key, "y") == 2, "the value should not be locked");
+	keyDel (key);
+}
+
+int main (void)
+{
+	test_name_and_value ();
+	test_meta ();
+	test_binary ();
+	test_flags ();
+
+	return nbError;
+}
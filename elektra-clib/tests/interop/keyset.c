@@ -0,0 +1,140 @@
+// Checks creating, copying and changing key sets.
+
+#include "kdb.h"
+#include "tests.h"
+
+static void test_new (void)
+{
+	KeySet * ks = ksNew (0, KS_END);
+	succeed_if (ksGetSize (ks) == 0, "an empty key set should have no keys");
+	ksDel (ks);
+
+	ks = ksNew (1, keyNew ("user:/b", KEY_END), keyNew ("system:/a", KEY_END), keyNew ("user:/a", KEY_END), KS_END);
+	succeed_if (ksGetSize (ks) == 3, "ksNew should append every key, not only alloc keys");
+	succeed_if_same_string (keyName (ksAtCursor (ks, 0)), "user:/a");
+	succeed_if_same_string (keyName (ksAtCursor (ks, 1)), "user:/b");
+	succeed_if_same_string (keyName (ksAtCursor (ks, 2)), "system:/a");
+	succeed_if (keyGetRef (ksAtCursor (ks, 0)) == 1, "the keys should be referenced by the key set");
+	succeed_if (keyIsLocked (ksAtCursor (ks, 0), KEY_LOCK_NAME), "the names of the keys should be locked");
+	ksDel (ks);
+
+	ks = ksNew (2, keyNew ("user:/a", KEY_VALUE, "1", KEY_END), keyNew ("user:/a", KEY_VALUE, "2", KEY_END), KS_END);
+	succeed_if (ksGetSize (ks) == 1, "ksNew should replace keys with the same name");
+	succeed_if_same_string (keyString (ksAtCursor (ks, 0)), "2");
+	ksDel (ks);
+}
+
+static void test_append_key (void)
+{
+	KeySet * ks = ksNew (0, KS_END);
+	Key * first = keyNew ("user:/a", KEY_VALUE, "1", KEY_END);
+	Key * second = keyNew ("user:/a", KEY_VALUE, "2", KEY_END);
+
+	keyIncRef (first);
+	keyIncRef (second);
+
+	succeed_if (ksAppendKey (ks, first) == 1, "ksAppendKey should return the new size");
+	succeed_if (keyGetRef (first) == 2, "ksAppendKey should reference the key");
+	succeed_if (ksCurrent (ks) == first, "ksAppendKey should move the cursor to the key");
+
+	succeed_if (ksAppendKey (ks, first) == 1, "appending a key twice should not add it again");
+	succeed_if (keyGetRef (first) == 2, "appending a key twice should reference it once");
+
+	succeed_if (ksAppendKey (ks, second) == 1, "a key with the same name should replace the key");
+	succeed_if (ksAtCursor (ks, 0) == second, "the new key should be in the key set");
+	succeed_if (keyGetRef (first) == 1, "the replaced key should no longer be referenced");
+	succeed_if (keyGetRef (second) == 2, "the new key should be referenced");
+	succeed_if_same_string (keyString (first), "1");
+
+	succeed_if (ksAppendKey (ks, 0) == -1, "ksAppendKey should reject NULL");
+	succeed_if (ksAppendKey (0, first) == -1, "ksAppendKey should reject a NULL key set");
+
+	ksDel (ks);
+	succeed_if (keyGetRef (second) == 1, "ksDel should release the keys");
+
+	keyDecRef (first);
+	keyDecRef (second);
+	keyDel (first);
+	keyDel (second);
+}
+
+static void test_append (void)
+{
+	KeySet * ks = ksNew (2, keyNew ("user:/a", KEY_VALUE, "1", KEY_END), keyNew ("user:/b", KEY_END), KS_END);
+	KeySet * other = ksNew (2, keyNew ("user:/a", KEY_VALUE, "2", KEY_END), keyNew ("user:/c", KEY_END), KS_END);
+
+	succeed_if (ksAppend (ks, other) == 3, "ksAppend should return the new size");
+	succeed_if_same_string (keyString (ksLookupByName (ks, "user:/a", 0)), "2");
+	succeed_if (ksAtCursor (ks, 0) == ksAtCursor (other, 0), "ksAppend should not copy the keys");
+	succeed_if (keyGetRef (ksAtCursor (other, 0)) == 2, "the keys should be referenced by both key sets");
+
+	ksDel (other);
+	succeed_if_same_string (keyName (ksAtCursor (ks, 2)), "user:/c");
+	ksDel (ks);
+}
+
+static void test_dup_copy_clear (void)
+{
+	KeySet * ks = ksNew (2, keyNew ("user:/a", KEY_END), keyNew ("user:/b", KEY_END), KS_END);
+
+	KeySet * dup = ksDup (ks);
+	succeed_if (ksGetSize (dup) == 2, "ksDup should copy every key");
+	succeed_if (ksAtCursor (dup, 0) == ksAtCursor (ks, 0), "ksDup should share the keys");
+	succeed_if (keyGetRef (ksAtCursor (ks, 0)) == 2, "the shared keys should be referenced twice");
+
+	KeySet * copy = ksNew (1, keyNew ("system:/x", KEY_END), KS_END);
+	succeed_if (ksCopy (copy, ks) == 1, "ksCopy should succeed");
+	succeed_if (ksGetSize (copy) == 2, "ksCopy should replace the keys");
+	succeed_if (ksLookupByName (copy, "system:/x", 0) == 0, "ksCopy should remove the old keys");
+	succeed_if (keyGetRef (ksAtCursor (ks, 1)) == 3, "ksCopy should share the keys");
+
+	succeed_if (ksCopy (copy, 0) == 0, "ksCopy from NULL should clear the key set");
+	succeed_if (ksGetSize (copy) == 0, "ksCopy from NULL should clear the key set");
+
+	succeed_if (ksClear (dup) == 0, "ksClear should succeed");
+	succeed_if (ksGetSize (dup) == 0, "ksClear should remove every key");
+	succeed_if (keyGetRef (ksAtCursor (ks, 0)) == 1, "ksClear should release the keys");
+
+	ksDel (copy);
+	ksDel (dup);
+	ksDel (ks);
+}
+
+static void test_pop (void)
+{
+	KeySet * ks = ksNew (2, keyNew ("user:/a", KEY_END), keyNew ("user:/b", KEY_END), KS_END);
+
+	Key * popped = ksPop (ks);
+	succeed_if_same_string (keyName (popped), "user:/b");
+	succeed_if (keyGetRef (popped) == 0, "ksPop should release the key");
+	succeed_if (ksGetSize (ks) == 1, "ksPop should remove the key");
+	keyDel (popped);
+
+	keyDel (ksPop (ks));
+	succeed_if (ksPop (ks) == 0, "ksPop should return NULL for an empty key set");
+
+	ksDel (ks);
+}
+
+static void test_del_referenced (void)
+{
+	KeySet * ks = ksNew (1, keyNew ("user:/a", KEY_END), KS_END);
+	Key * key = ksAtCursor (ks, 0);
+
+	succeed_if (keyDel (key) == 1, "keyDel should not delete keys in key sets");
+	succeed_if_same_string (keyName (key), "user:/a");
+
+	ksDel (ks);
+}
+
+int main (void)
+{
+	test_new ();
+	test_append_key ();
+	test_append ();
+	test_dup_copy_clear ();
+	test_pop ();
+	test_del_referenced ();
+
+	return nbError;
+}
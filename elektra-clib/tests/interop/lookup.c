@@ -0,0 +1,103 @@
+// Checks looking up keys, with the options of ksLookup.
+
+#include "kdb.h"
+#include "tests.h"
+
+static void test_lookup (void)
+{
+	KeySet * ks = ksNew (3, keyNew ("user:/a", KEY_END), keyNew ("user:/b", KEY_END), keyNew ("user:/c", KEY_END), KS_END);
+	Key * search = keyNew ("user:/b", KEY_END);
+
+	Key * found = ksLookup (ks, search, 0);
+	succeed_if_same_string (keyName (found), "user:/b");
+	succeed_if (ksCurrent (ks) == found, "ksLookup should move the cursor to the found key");
+	succeed_if (ksLookupByName (ks, "user:/x", 0) == 0, "ksLookup should not find missing keys");
+	succeed_if (ksLookup (ks, 0, 0) == 0, "ksLookup should reject NULL");
+
+	// only the keys after the cursor
+	ksRewind (ks);
+	ksNext (ks);
+	succeed_if (ksLookupByName (ks, "user:/b", KDB_O_NOALL) == found, "KDB_O_NOALL should find keys after the cursor");
+	succeed_if (ksLookupByName (ks, "user:/a", KDB_O_NOALL) == 0, "KDB_O_NOALL should not find keys before the cursor");
+
+	keyDel (search);
+	ksDel (ks);
+}
+
+static void test_pop_and_del (void)
+{
+	KeySet * ks = ksNew (2, keyNew ("user:/a", KEY_END), keyNew ("user:/b", KEY_END), KS_END);
+	Key * search = keyNew ("user:/a", KEY_END);
+
+	Key * popped = ksLookup (ks, search, KDB_O_POP);
+	succeed_if_same_string (keyName (popped), "user:/a");
+	succeed_if (keyGetRef (popped) == 0, "KDB_O_POP should release the found key");
+	succeed_if (ksGetSize (ks) == 1, "KDB_O_POP should remove the found key");
+	succeed_if (ksCurrent (ks) == 0, "KDB_O_POP should rewind the key set");
+	succeed_if (ksLookup (ks, search, 0) == 0, "a popped key should no longer be found");
+	keyDel (popped);
+
+	// the search key is deleted, even if nothing is found
+	succeed_if_same_string (keyName (ksLookup (ks, keyNew ("user:/b", KEY_END), KDB_O_DEL)), "user:/b");
+	succeed_if (ksLookup (ks, keyNew ("user:/x", KEY_END), KDB_O_DEL) == 0, "ksLookup should not find missing keys");
+
+	popped = ksLookup (ks, keyNew ("/b", KEY_END), KDB_O_DEL | KDB_O_POP);
+	succeed_if_same_string (keyName (popped), "user:/b");
+	succeed_if (ksGetSize (ks) == 0, "a cascading lookup should pop the found key");
+	keyDel (popped);
+
+	keyDel (search);
+	ksDel (ks);
+}
+
+static void test_cascading (void)
+{
+	KeySet * ks = ksNew (4, keyNew ("system:/a", KEY_VALUE, "system", KEY_END), keyNew ("user:/a", KEY_VALUE, "user", KEY_END),
+			     keyNew ("/b", KEY_VALUE, "cascading", KEY_END), keyNew ("default:/c", KEY_VALUE, "default", KEY_END), KS_END);
+
+	succeed_if_same_string (keyString (ksLookupByName (ks, "/a", 0)), "user");
+	succeed_if (ksLookupByName (ks, "/a", KDB_O_NOCASCADING) == 0, "KDB_O_NOCASCADING should only look up the name");
+	succeed_if_same_string (keyString (ksLookupByName (ks, "/b", KDB_O_NOCASCADING)), "cascading");
+	succeed_if_same_string (keyString (ksLookupByName (ks, "/c", 0)), "default");
+	succeed_if (ksLookupByName (ks, "/c", KDB_O_NODEFAULT) == 0, "KDB_O_NODEFAULT should skip the default namespace");
+
+	ksDel (ks);
+}
+
+static void test_spec (void)
+{
+	KeySet * ks = ksNew (4, keyNew ("spec:/a", KEY_META, "override/#0", "/b", KEY_META, "fallback/#0", "/c", KEY_END),
+			     keyNew ("user:/a", KEY_VALUE, "a", KEY_END), keyNew ("system:/b", KEY_VALUE, "b", KEY_END),
+			     keyNew ("user:/c", KEY_VALUE, "c", KEY_END), KS_END);
+
+	Key * found = ksLookupByName (ks, "/a", 0);
+	succeed_if_same_string (keyString (found), "b");
+	succeed_if (ksCurrent (ks) == found, "a spec lookup should move the cursor to the found key");
+	succeed_if_same_string (keyString (ksLookupByName (ks, "/a", KDB_O_NOSPEC)), "a");
+	succeed_if_same_string (keyString (ksLookup (ks, ksLookupByName (ks, "spec:/a", 0), KDB_O_SPEC)), "b");
+
+	// the namespaces of the spec key, then its fallback
+	keySetMeta (ksLookupByName (ks, "spec:/a", 0), "override/#0", 0);
+	keySetMeta (ksLookupByName (ks, "spec:/a", 0), "namespace/#0", "system");
+	succeed_if_same_string (keyString (ksLookupByName (ks, "/a", 0)), "c");
+
+	keySetMeta (ksLookupByName (ks, "spec:/a", 0), "namespace/#0", "user");
+	succeed_if_same_string (keyString (ksLookupByName (ks, "/a", 0)), "a");
+
+	found = ksLookupByName (ks, "/a", KDB_O_POP);
+	succeed_if_same_string (keyName (found), "user:/a");
+	succeed_if_same_string (keyString (ksLookupByName (ks, "/a", 0)), "c");
+	keyDel (found);
+
+	ksDel (ks);
+}
+
+int main (void)
+{
+	test_lookup ();
+	test_pop_and_del ();
+	test_cascading ();
+	test_spec ();
+
+	return nbError;
+}
@@ -0,0 +1,136 @@
+// Checks the metadata of keys, and the key set from keyMeta.
+
+#include "kdb.h"
+#include "tests.h"
+
+static void test_get_and_set (void)
+{
+	Key * key = keyNew ("user:/a", KEY_META, "type", "long", KEY_END);
+
+	const Key * type = keyGetMeta (key, "type");
+	succeed_if_same_string (keyName (type), "meta:/type");
+	succeed_if_same_string (keyString (type), "long");
+	succeed_if (keyGetMeta (key, "meta:/type") == type, "keyGetMeta should take names with the namespace");
+	succeed_if (keyGetMeta (key, "other") == 0, "keyGetMeta should not find missing meta keys");
+
+	// changing other meta keys keeps the meta key
+	succeed_if (keySetMeta (key, "other", "x") == 2, "keySetMeta should return the size of the value");
+	succeed_if (keyGetMeta (key, "type") == type, "the meta key should not change");
+	succeed_if_same_string (keyString (type), "long");
+
+	succeed_if (keySetMeta (key, "other", 0) == 0, "keySetMeta should remove meta keys");
+	succeed_if (keyGetMeta (key, "other") == 0, "the meta key should be removed");
+	succeed_if_same_string (keyString (type), "long");
+
+	// meta keys cannot be changed
+	succeed_if (keyIsLocked (type, KEY_LOCK_VALUE), "meta keys should be locked");
+	succeed_if (keySetString ((Key *) type, "int") == -1, "meta keys should be locked");
+
+	// a meta key can outlive its key
+	keyIncRef ((Key *) type);
+	keyDel (key);
+	succeed_if_same_string (keyString (type), "long");
+	keyDecRef ((Key *) type);
+	keyDel ((Key *) type);
+}
+
+static void test_not_utf8 (void)
+{
+	// "café" and "naïve" in Latin-1
+	Key * key = keyNew ("user:/a", KEY_META, "caf\xe9", "na\xefve", KEY_END);
+	succeed_if (key != 0, "keyNew should take meta keys that are not UTF-8");
+	succeed_if_same_string (keyString (keyGetMeta (key, "caf\xe9")), "na\xefve");
+
+	succeed_if (keySetMeta (key, "\xe9t\xe9", "\xff") == 2, "keySetMeta should take meta keys that are not UTF-8");
+	succeed_if_same_string (keyString (keyGetMeta (key, "meta:/\xe9t\xe9")), "\xff");
+
+	Key * copy = keyNew ("user:/b", KEY_END);
+	succeed_if (keyCopyMeta (copy, key, "caf\xe9") == 1, "keyCopyMeta should copy meta keys that are not UTF-8");
+	succeed_if_same_string (keyString (keyGetMeta (copy, "caf\xe9")), "na\xefve");
+
+	succeed_if (keySetMeta (key, "\xe9t\xe9", 0) == 0, "keySetMeta should remove meta keys that are not UTF-8");
+	succeed_if (keyGetMeta (key, "\xe9t\xe9") == 0, "the meta key should be removed");
+
+	keyDel (copy);
+	keyDel (key);
+}
+
+static void test_key_meta (void)
+{
+	Key * key = keyNew ("user:/a", KEY_META, "type", "long", KEY_END);
+	KeySet * meta = keyMeta (key);
+
+	succeed_if (ksGetSize (meta) == 1, "keyMeta should return every meta key");
+	succeed_if (ksAtCursor (meta, 0) == keyGetMeta (key, "type"), "keyMeta should return the meta keys of keyGetMeta");
+
+	// the key set follows the key
+	keySetMeta (key, "other", "x");
+	succeed_if (keyMeta (key) == meta, "keyMeta should always return the same key set");
+	succeed_if (ksGetSize (meta) == 2, "the key set should contain new meta keys");
+	succeed_if_same_string (keyString (ksLookupByName (meta, "meta:/other", 0)), "x");
+
+	succeed_if (ksDel (meta) > 0, "ksDel should not delete the metadata of a key");
+	succeed_if (ksGetSize (meta) == 2, "the key set should still be there");
+
+	keyDel (key);
+}
+
+static void test_write_through (void)
+{
+	Key * key = keyNew ("user:/a", KEY_META, "type", "long", KEY_END);
+	KeySet * meta = keyMeta (key);
+
+	Key * added = keyNew ("meta:/x", KEY_VALUE, "1", KEY_END);
+	succeed_if (ksAppendKey (meta, added) == 2, "meta keys should be added");
+	succeed_if (keyGetMeta (key, "x") == added, "an added meta key should be metadata of the key");
+	succeed_if (keyIsLocked (added, KEY_LOCK_VALUE), "an added meta key should be locked");
+
+	Key * other = keyNew ("user:/x", KEY_END);
+	succeed_if (ksAppendKey (meta, other) == -1, "only meta keys should be added");
+	succeed_if (keyGetMeta (key, "meta:/user:/x") == 0, "the key should not be added");
+	keyDel (other);
+
+	Key * popped = ksLookupByName (meta, "meta:/x", KDB_O_POP);
+	succeed_if (popped == added, "KDB_O_POP should pop the meta key");
+	succeed_if (keyGetMeta (key, "x") == 0, "a popped meta key should be removed from the key");
+	keyDel (popped);
+
+	Key * cutpoint = keyNew ("meta:/type", KEY_END);
+	ksDel (ksCut (meta, cutpoint));
+	succeed_if (keyGetMeta (key, "type") == 0, "cut meta keys should be removed from the key");
+	keyDel (cutpoint);
+
+	keySetMeta (key, "y", "2");
+	succeed_if (ksClear (meta) == 0, "the metadata should be cleared");
+	succeed_if (keyGetMeta (key, "y") == 0, "ksClear should remove the metadata of the key");
+
+	keyDel (key);
+}
+
+static void test_locked_meta (void)
+{
+	Key * key = keyNew ("user:/a", KEY_META, "type", "long", KEY_END);
+	KeySet * meta = keyMeta (key);
+	keyLock (key, KEY_LOCK_META);
+
+	Key * added = keyNew ("meta:/x", KEY_VALUE, "1", KEY_END);
+	succeed_if (ksAppendKey (meta, added) == -1, "locked metadata should not be changed");
+	succeed_if (ksPop (meta) == 0, "locked metadata should not be changed");
+	succeed_if (ksClear (meta) == -1, "locked metadata should not be changed");
+	succeed_if (ksGetSize (meta) == 1, "locked metadata should not be changed");
+	succeed_if_same_string (keyString (keyGetMeta (key, "type")), "long");
+	keyDel (added);
+
+	keyDel (key);
+}
+
+int main (void)
+{
+	test_get_and_set ();
+	test_not_utf8 ();
+	test_key_meta ();
+	test_write_through ();
+	test_locked_meta ();
+
+	return nbError;
+}
@@ -0,0 +1,89 @@
+// A storage plugin writing one `name<separator>value` line per key, with
+// the separator from the configuration key `user:/separator`.
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "kdbplugin.h"
+
+static int setError (Key * parentKey, const char * number, const char * reason)
+{
+	keySetMeta (parentKey, "error/number", number);
+	keySetMeta (parentKey, "error/reason", reason);
+	return -1;
+}
+
+static int linesOpen (Plugin * handle, Key * errorKey)
+{
+	Key * separator = ksLookupByName (elektraPluginGetConfig (handle), "user:/separator", 0);
+	elektraPluginSetData (handle, strdup (separator ? keyString (separator) : "="));
+	return 1;
+}
+
+static int linesClose (Plugin * handle, Key * errorKey)
+{
+	free (elektraPluginGetData (handle));
+	return 1;
+}
+
+static int linesGet (Plugin * handle, KeySet * returned, Key * parentKey)
+{
+	if (!strcmp (keyName (parentKey), "system:/elektra/modules/lines"))
+	{
+		ksAppendKey (returned, keyNew ("system:/elektra/modules/lines", KEY_END));
+		ksAppendKey (returned, keyNew ("system:/elektra/modules/lines/infos/provides", KEY_VALUE, "storage", KEY_END));
+		return 1;
+	}
+
+	FILE * file = fopen (keyString (parentKey), "r");
+	if (!file) return 0;
+
+	const char * separator = elektraPluginGetData (handle);
+	char line[1024];
+
+	while (fgets (line, sizeof line, file))
+	{
+		line[strcspn (line, "\n")] = 0;
+
+		char * value = strstr (line, separator);
+		if (!value)
+		{
+			fclose (file);
+			return setError (parentKey, "C03100", "a line has no separator");
+		}
+
+		*value = 0;
+		ksAppendKey (returned, keyNew (line, KEY_VALUE, value + strlen (separator), KEY_END));
+	}
+
+	fclose (file);
+	return 1;
+}
+
+static int linesSet (Plugin * handle, KeySet * returned, Key * parentKey)
+{
+	FILE * file = fopen (keyString (parentKey), "w");
+	if (!file) return setError (parentKey, "C01100", "could not open the file");
+
+	Key * key;
+	ksRewind (returned);
+	while ((key = ksNext (returned)))
+	{
+		fprintf (file, "%s%s%s\n", keyName (key), (const char *) elektraPluginGetData (handle), keyString (key));
+	}
+
+	fclose (file);
+	return 1;
+}
+
+Plugin * ELEKTRA_PLUGIN_EXPORT
+{
+	// clang-format off
+	return elektraPluginExport ("lines",
+		ELEKTRA_PLUGIN_OPEN, &linesOpen,
+		ELEKTRA_PLUGIN_CLOSE, &linesClose,
+		ELEKTRA_PLUGIN_GET, &linesGet,
+		ELEKTRA_PLUGIN_SET, &linesSet,
+		ELEKTRA_PLUGIN_END);
+}
@@ -0,0 +1,35 @@
+// The checks of libelektra's C test framework that the interop tests use.
+// A test program prints every failed check and exits with the number of
+// failures.
+
+#ifndef TESTS_H
+#define TESTS_H
+
+#include <stdio.h>
+#include <string.h>
+
+static int nbError;
+
+#define succeed_if(x, message)                                                                                                     \
+	do                                                                                                                         \
+	{                                                                                                                          \
+		if (!(x))                                                                                                          \
+		{                                                                                                                  \
+			nbError++;                                                                                                 \
+			printf ("%s:%d: %s\n", __FILE__, __LINE__, message);                                                       \
+		}                                                                                                                  \
+	} while (0)
+
+#define succeed_if_same_string(s1, s2)                                                                                             \
+	do                                                                                                                         \
+	{                                                                                                                          \
+		const char * a = (s1);                                                                                             \
+		const char * b = (s2);                                                                                             \
+		if (!a || !b || strcmp (a, b))                                                                                     \
+		{                                                                                                                  \
+			nbError++;                                                                                                 \
+			printf ("%s:%d: \"%s\" is not \"%s\"\n", __FILE__, __LINE__, a ? a : "(null)", b ? b : "(null)");          \
+		}                                                                                                                  \
+	} while (0)
+
+#endif
@@ -0,0 +1,141 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::kdb::KdbError;
+use crate::key::{Key, KeyName, KeySet};
+use crate::plugin::PluginInstance;
+
+/// A file holding the keys of a mountpoint, read and written by a storage
+/// plugin.
+///
+/// The backend remembers what the file looked like when it was last read or
+/// written, so that [`Kdb`](crate::kdb::Kdb) can tell whether it changed
+/// since. Writing happens in two phases: [`Backend::prepare`] lets the
+/// plugin write a temporary file next to the storage, which
+/// [`Backend::commit`] renames over it or [`Backend::abort`] removes again.
+#[derive(Debug)]
+pub struct Backend {
+    mountpoint: KeyName,
+    path: PathBuf,
+    storage: PluginInstance,
+    /// The content of the file as of the last read or write, where
+    /// `Some(None)` means that there was no file yet.
+    last_read: Option<Option<Vec<u8>>>,
+}
+
+impl Backend {
+    pub fn new(mountpoint: KeyName, path: PathBuf, storage: PluginInstance) -> Backend {
+        Backend {
+            mountpoint,
+            path,
+            storage,
+            last_read: None,
+        }
+    }
+
+    pub fn mountpoint(&self) -> &KeyName {
+        &self.mountpoint
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn storage(&self) -> &PluginInstance {
+        &self.storage
+    }
+
+    /// Reads the current content of the file, `None` if it does not exist.
+    pub(crate) fn read(&self) -> Result<Option<Vec<u8>>, KdbError> {
+        match fs::read(&self.path) {
+            Ok(content) => Ok(Some(content)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(self.resource_error(error)),
+        }
+    }
+
+    pub(crate) fn last_read(&self) -> Option<&Option<Vec<u8>>> {
+        self.last_read.as_ref()
+    }
+
+    pub(crate) fn set_last_read(&mut self, content: Option<Vec<u8>>) {
+        self.last_read = Some(content);
+    }
+
+    /// Reads the keys from the file with the storage plugin.
+    pub(crate) fn load(&mut self) -> Result<KeySet, KdbError> {
+        let mut keys = KeySet::default();
+        let mut parent = self.parent(&self.path)?;
+
+        self.storage.get(&mut keys, &mut parent)?;
+
+        Ok(keys)
+    }
+
+    /// Lets the storage plugin write `keys` to the temporary file, creating
+    /// missing directories. Returns the content that was written.
+    pub(crate) fn prepare(&mut self, keys: &mut KeySet) -> Result<Vec<u8>, KdbError> {
+        let temporary = self.temporary_path();
+        let mut parent = self.parent(&temporary)?;
+
+        if let Some(directory) = self.path.parent().filter(|directory| !directory.as_os_str().is_empty()) {
+            fs::create_dir_all(directory).map_err(|error| self.resource_error(error))?;
+        }
+
+        let written = self.storage.set(keys, &mut parent)
+            .and_then(|_| fs::read(&temporary).map_err(|error| self.resource_error(error)));
+
+        if written.is_err() {
+            self.abort(keys);
+        }
+
+        written
+    }
+
+    /// Replaces the file with the one written by [`Backend::prepare`].
+    pub(crate) fn commit(&mut self, keys: &mut KeySet, content: Vec<u8>) -> Result<(), KdbError> {
+        if let Err(error) = fs::rename(self.temporary_path(), &self.path) {
+            self.abort(keys);
+            return Err(self.resource_error(error));
+        }
+
+        self.last_read = Some(Some(content));
+
+        let mut parent = self.parent(&self.path)?;
+        self.storage.commit(keys, &mut parent)?;
+
+        Ok(())
+    }
+
+    /// Removes the file written by [`Backend::prepare`], if there is one.
+    pub(crate) fn abort(&mut self, keys: &mut KeySet) {
+        let temporary = self.temporary_path();
+        let _ = fs::remove_file(&temporary);
+
+        if let Ok(mut parent) = self.parent(&temporary) {
+            let _ = self.storage.error(keys, &mut parent);
+        }
+    }
+
+    /// The parent key for the storage plugin, with the file to use as value.
+    fn parent(&self, path: &Path) -> Result<Key, KdbError> {
+        let path = path.to_str()
+            .ok_or_else(|| KdbError::Installation(format!("{} is not valid UTF-8", path.display())))?;
+
+        let mut parent = Key::new(self.mountpoint.clone());
+        parent.set_string(path).expect("a new key is not locked");
+
+        Ok(parent)
+    }
+
+    fn temporary_path(&self) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(".tmp");
+        PathBuf::from(path)
+    }
+
+    fn resource_error(&self, error: io::Error) -> KdbError {
+        KdbError::Resource(format!("{}: {}", self.path.display(), error))
+    }
+}
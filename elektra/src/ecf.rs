@@ -0,0 +1,263 @@
+use std::fs;
+use std::io;
+
+use crate::kdb::KdbError;
+use crate::key::{Key, KeyBuilder, KeyName, KeySet, KeyValue};
+use crate::plugin::{module_name, Plugin};
+
+/// The built-in storage plugin, reading and writing the file named by the
+/// value of the parent key. A missing file holds no keys.
+#[derive(Debug, Default)]
+pub struct Ecf;
+
+impl Plugin for Ecf {
+    fn name(&self) -> &str {
+        "ecf"
+    }
+
+    fn contract(&self) -> KeySet {
+        let module = module_name(self.name());
+        let mut contract = KeySet::default();
+
+        contract.append_key(Key::new(module.clone()));
+
+        for (info, value) in [("provides", "storage"), ("description", "Stores keys with their values and metadata")] {
+            let mut name = module.clone();
+            name.append_part(b"infos");
+            name.append_part(info.as_bytes());
+
+            let mut key = Key::new(name);
+            key.set_string(value).expect("a new key is not locked");
+            contract.append_key(key);
+        }
+
+        contract
+    }
+
+    fn get(&mut self, returned: &mut KeySet, parent: &mut Key) -> Result<bool, KdbError> {
+        let path = file(parent)?;
+
+        let content = match fs::read(&path) {
+            Ok(content) => content,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(error) => return Err(KdbError::Resource(format!("{}: {}", path, error))),
+        };
+
+        let keys = parse(&content)
+            .map_err(|reason| KdbError::ValidationSyntactic(format!("{}: {}", path, reason)))?;
+
+        for (_, key) in keys.values() {
+            returned.append_shared_key(key.clone());
+        }
+
+        Ok(true)
+    }
+
+    fn set(&mut self, returned: &mut KeySet, parent: &mut Key) -> Result<bool, KdbError> {
+        let path = file(parent)?;
+
+        fs::write(&path, serialize(returned))
+            .map_err(|error| KdbError::Resource(format!("{}: {}", path, error)))?;
+
+        Ok(true)
+    }
+}
+
+fn file(parent: &Key) -> Result<String, KdbError> {
+    match parent.string() {
+        Some(path) if !path.is_empty() => Ok(path.to_string()),
+        _ => Err(KdbError::Interface(format!("the value of {} has to name the file", parent.name()))),
+    }
+}
+
+const HEADER: &[u8] = b"elektra-rust 1\n";
+
+/// Writes the keys in a line based format, where each name and value is
+/// preceded by its length, so that they can contain arbitrary bytes:
+///
+/// ```text
+/// key <name length> <string|binary|none> <value length>
+/// <name>
+/// <value>
+/// meta <name length> <value length>
+/// <name>
+/// <value>
+/// ```
+fn serialize(ks: &KeySet) -> Vec<u8> {
+    let mut out = HEADER.to_vec();
+
+    for (name, key) in ks.values() {
+        let key = key.borrow();
+        let name = name.escaped();
+
+        let (kind, value) = match key.value() {
+            Some(KeyValue::String(value)) => ("string", &value[..]),
+            Some(KeyValue::Binary(value)) => ("binary", &value[..]),
+            None => ("none", &[][..]),
+        };
+
+        out.extend(format!("key {} {} {}\n", name.len(), kind, value.len()).as_bytes());
+        write_field(&mut out, &name);
+        write_field(&mut out, value);
+
+        for (meta_name, meta_key) in key.meta_keys().values() {
+            let meta_name = meta_name.escaped();
+            let meta_key = meta_key.borrow();
+            let meta_value = meta_key.value().map(KeyValue::as_bytes).unwrap_or_default();
+
+            out.extend(format!("meta {} {}\n", meta_name.len(), meta_value.len()).as_bytes());
+            write_field(&mut out, &meta_name);
+            write_field(&mut out, meta_value);
+        }
+    }
+
+    out
+}
+
+fn write_field(out: &mut Vec<u8>, field: &[u8]) {
+    out.extend(field);
+    out.push(b'\n');
+}
+
+fn parse(content: &[u8]) -> Result<KeySet, &'static str> {
+    let mut reader = Reader { content, position: 0 };
+
+    if reader.line() != Some(&HEADER[..HEADER.len() - 1]) {
+        return Err("the storage does not start with the expected header");
+    }
+
+    let mut ks = KeySet::default();
+    let mut builder: Option<KeyBuilder> = None;
+
+    while let Some(line) = reader.line() {
+        let line = std::str::from_utf8(line).map_err(|_| "invalid line")?;
+        let fields: Vec<&str> = line.split(' ').collect();
+
+        match fields[..] {
+            ["key", name_length, kind, value_length] => {
+                if let Some(builder) = builder.take() {
+                    ks.append_key(builder.build().map_err(|_| "invalid key")?);
+                }
+
+                let name = reader.field(name_length).ok_or("invalid key name")?;
+                let value = reader.field(value_length).ok_or("invalid key value")?;
+
+                let name = KeyName::from_bytes(name).map_err(|_| "invalid key name")?;
+                let key = KeyBuilder::new(name);
+
+                builder = Some(match kind {
+                    "string" => key.value(KeyValue::String(value.to_vec())),
+                    "binary" => key.value(KeyValue::Binary(value.to_vec())),
+                    "none" => key,
+                    _ => return Err("invalid value type"),
+                });
+            }
+            ["meta", name_length, value_length] => {
+                let name = reader.field(name_length).ok_or("invalid meta name")?;
+                let value = reader.field(value_length).ok_or("invalid meta value")?;
+
+                builder = match builder.take() {
+                    Some(builder) => Some(builder.meta(name, value)),
+                    None => return Err("meta without a key"),
+                };
+            }
+            _ => return Err("invalid line"),
+        }
+    }
+
+    if let Some(builder) = builder {
+        ks.append_key(builder.build().map_err(|_| "invalid key")?);
+    }
+
+    Ok(ks)
+}
+
+struct Reader<'a> {
+    content: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn line(&mut self) -> Option<&'a [u8]> {
+        let rest = &self.content[self.position..];
+        let end = rest.iter().position(|&byte| byte == b'\n')?;
+
+        self.position += end + 1;
+        Some(&rest[..end])
+    }
+
+    /// Reads a field of `length` bytes, followed by a newline.
+    fn field(&mut self, length: &str) -> Option<&'a [u8]> {
+        let length: usize = length.parse().ok()?;
+        let rest = &self.content[self.position..];
+
+        if rest.len() <= length || rest[length] != b'\n' {
+            return None;
+        }
+
+        self.position += length + 1;
+        Some(&rest[..length])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_ecf_format() {
+        let mut ks = KeySet::default();
+        ks.append_key(KeyBuilder::from_str("user:/app/port")
+            .unwrap()
+            .value(KeyValue::String(b"8080".to_vec()))
+            .meta("type", "long")
+            .build()
+            .unwrap());
+        ks.append_key(KeyBuilder::from_str("user:/app/data\\/x")
+            .unwrap()
+            .value(KeyValue::Binary(vec![0, b'\n', 255]))
+            .build()
+            .unwrap());
+        ks.append_key(KeyBuilder::from_str("user:/app/none").unwrap().build().unwrap());
+
+        let parsed = parse(&serialize(&ks)).unwrap();
+        assert_eq!(serialize(&parsed), serialize(&ks));
+
+        let port = parsed.get(&KeyName::from_str("user:/app/port").unwrap()).unwrap();
+        assert_eq!(port.string(), Some("8080"));
+        assert_eq!(port.meta("type").unwrap().string(), Some("long"));
+        drop(port);
+
+        let data = parsed.get(&KeyName::from_str("user:/app/data\\/x").unwrap()).unwrap();
+        assert_eq!(data.binary(), Some(&[0, b'\n', 255][..]));
+        drop(data);
+
+        assert!(parsed.get(&KeyName::from_str("user:/app/none").unwrap()).unwrap().value().is_none());
+    }
+
+    #[test]
+    fn test_ecf_binary_meta() {
+        let mut ks = KeySet::default();
+        ks.append_key(KeyBuilder::from_str("user:/app/port")
+            .unwrap()
+            .meta(b"caf\xe9", b"\xff")
+            .build()
+            .unwrap());
+
+        let parsed = parse(&serialize(&ks)).unwrap();
+        assert_eq!(serialize(&parsed), serialize(&ks));
+
+        let port = parsed.get(&KeyName::from_str("user:/app/port").unwrap()).unwrap();
+        let meta = port.meta(b"caf\xe9").unwrap();
+        assert_eq!(meta.value().map(KeyValue::as_bytes), Some(&b"\xff"[..]));
+    }
+
+    #[test]
+    fn test_ecf_parse_errors() {
+        assert!(parse(b"elektra-rust 1\nkey 8 string 1\nuser:/a\nx\n").is_err());
+        assert!(parse(b"elektra-rust 1\nmeta 1 1\na\nb\n").is_err());
+        assert!(parse(b"something else\n").is_err());
+        assert_eq!(parse(b"elektra-rust 1\nkey 7 string 1\nuser:/a\nx\n").unwrap().size(), 1);
+    }
+}
@@ -0,0 +1,548 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::key::{Key, KeyName, KeyNamespace, KeySet};
+use crate::mount::{Mountpoints, PERSISTENT_NAMESPACES};
+
+/// The contract key naming the bootstrap file, see [`Mountpoints`].
+pub const STORAGE_CONTRACT: &str = "system:/elektra/contract/kdb/storage";
+
+/// The bootstrap file, if the contract does not name one.
+pub const DEFAULT_STORAGE: &str = "/etc/kdb/elektra-rust.ecf";
+
+/// The errors of a [`Kdb`] session, with libelektra's error codes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KdbError {
+    Resource(String),
+    Installation(String),
+    Interface(String),
+    ConflictingState(String),
+    ValidationSyntactic(String),
+    /// Storing failed after some backends, which the reason names, had
+    /// stored the keys already.
+    PartialCommit(String),
+}
+
+impl KdbError {
+    /// The libelektra error code, e.g. `C01100`.
+    pub fn number(&self) -> &'static str {
+        match self {
+            KdbError::Resource(_) | KdbError::PartialCommit(_) => "C01100",
+            KdbError::Installation(_) => "C01200",
+            KdbError::Interface(_) => "C01320",
+            KdbError::ConflictingState(_) => "C02000",
+            KdbError::ValidationSyntactic(_) => "C03100",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            KdbError::Resource(_) | KdbError::PartialCommit(_) => "Resource",
+            KdbError::Installation(_) => "Installation",
+            KdbError::Interface(_) => "Interface",
+            KdbError::ConflictingState(_) => "Conflicting State",
+            KdbError::ValidationSyntactic(_) => "Validation Syntactic",
+        }
+    }
+
+    pub fn reason(&self) -> &str {
+        match self {
+            KdbError::Resource(reason)
+            | KdbError::Installation(reason)
+            | KdbError::Interface(reason)
+            | KdbError::ConflictingState(reason)
+            | KdbError::ValidationSyntactic(reason)
+            | KdbError::PartialCommit(reason) => reason,
+        }
+    }
+
+    /// Reports the error on `key` the way libelektra does, in the metadata
+    /// `error/number`, `error/description` and `error/reason`.
+    pub fn set_on(&self, key: &mut Key) {
+        // a key with locked metadata just does not get the report
+        let _ = key.set_meta("error", "")
+            .and_then(|_| key.set_meta("error/number", self.number()))
+            .and_then(|_| key.set_meta("error/description", self.description()))
+            .and_then(|_| key.set_meta("error/reason", self.reason()));
+    }
+
+    /// Reads an error reported on `key`, like by [`KdbError::set_on`]. Error
+    /// numbers without a variant of their own are mapped to the variant of
+    /// their category, unknown ones to [`KdbError::Interface`].
+    pub fn from_key(key: &Key) -> Option<KdbError> {
+        let number = key.meta("error/number")?.string()?.to_string();
+        let reason = key.meta("error/reason")
+            .and_then(|reason| reason.string().map(str::to_string))
+            .unwrap_or_default();
+
+        Some(match &number[..] {
+            number if number.starts_with("C011") => KdbError::Resource(reason),
+            number if number.starts_with("C012") => KdbError::Installation(reason),
+            number if number.starts_with("C02") => KdbError::ConflictingState(reason),
+            number if number.starts_with("C03") => KdbError::ValidationSyntactic(reason),
+            _ => KdbError::Interface(reason),
+        })
+    }
+}
+
+impl fmt::Display for KdbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}): {}", self.description(), self.number(), self.reason())
+    }
+}
+
+impl std::error::Error for KdbError {}
+
+/// A session with the key database.
+///
+/// Like in libelektra, keys have to be retrieved with [`Kdb::get`] before
+/// they can be stored with [`Kdb::set`] for the same parent. `set` fails
+/// with [`KdbError::ConflictingState`] if a backend was changed by someone
+/// else since then, and writes in two phases: all backends first write
+/// temporary files, which only replace the stored ones once all of them
+/// were written. If replacing fails for a backend after others replaced
+/// theirs, `set` fails with [`KdbError::PartialCommit`].
+pub struct Kdb {
+    mountpoints: Mountpoints,
+    /// The parents passed to `get` so far.
+    parents: Vec<KeyName>,
+}
+
+impl Kdb {
+    /// Opens a session. The contract may name the bootstrap file, which
+    /// holds the mountpoint configuration, in the key [`STORAGE_CONTRACT`],
+    /// otherwise [`DEFAULT_STORAGE`] is used.
+    pub fn open(contract: &KeySet, error_key: &mut Key) -> Result<Kdb, KdbError> {
+        let result = Kdb::bootstrap(contract);
+
+        if let Err(error) = &result {
+            error.set_on(error_key);
+        }
+
+        result
+    }
+
+    /// The backends of this session, see [`Mountpoints`].
+    pub fn mountpoints(&self) -> &Mountpoints {
+        &self.mountpoints
+    }
+
+    /// Retrieves the keys below `parent` into `returned`, replacing the keys
+    /// below `parent` that were there. Returns `false` without touching
+    /// `returned` if nothing changed since the last call for `parent`.
+    pub fn get(&mut self, returned: &mut KeySet, parent: &mut Key) -> Result<bool, KdbError> {
+        let result = self.get_keys(returned, parent.key_name());
+
+        if let Err(error) = &result {
+            error.set_on(parent);
+        }
+
+        result
+    }
+
+    /// Stores the keys below `parent` from `ks`. Returns `false` if they
+    /// are stored already.
+    pub fn set(&mut self, ks: &mut KeySet, parent: &mut Key) -> Result<bool, KdbError> {
+        let result = self.set_keys(ks, parent.key_name());
+
+        if let Err(error) = &result {
+            error.set_on(parent);
+        }
+
+        result
+    }
+
+    /// Ends the session. Nothing is kept open between the calls, so this
+    /// cannot fail yet, but backends may need to release resources here.
+    pub fn close(self, _error_key: &mut Key) -> Result<(), KdbError> {
+        Ok(())
+    }
+
+    fn bootstrap(contract: &KeySet) -> Result<Kdb, KdbError> {
+        let name = KeyName::from_str(STORAGE_CONTRACT)
+            .expect("the storage contract is a valid key name");
+
+        let bootstrap = match contract.get(&name) {
+            Some(key) => match key.string() {
+                Some(path) if !path.is_empty() => PathBuf::from(path),
+                _ => return Err(KdbError::Interface(format!("{} has to be a path", STORAGE_CONTRACT))),
+            },
+            None => PathBuf::from(DEFAULT_STORAGE),
+        };
+
+        Ok(Kdb {
+            mountpoints: Mountpoints::bootstrap(bootstrap)?,
+            parents: Vec::new(),
+        })
+    }
+
+    fn get_keys(&mut self, returned: &mut KeySet, parent: &KeyName) -> Result<bool, KdbError> {
+        let covered = self.is_covered(parent);
+        let backends = self.mountpoints.involved(parent);
+
+        let contents = backends.iter()
+            .map(|&index| self.mountpoints.backend(index).read())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let unchanged = backends.iter()
+            .zip(&contents)
+            .all(|(&index, content)| self.mountpoints.backend(index).last_read() == Some(content));
+
+        if covered && unchanged {
+            return Ok(false);
+        }
+
+        let mut fetched = KeySet::default();
+
+        for &index in &backends {
+            let keys = self.mountpoints.backend_mut(index).load()?;
+
+            // keys that belong to another mountpoint are not this backend's
+            if let Some(keys) = self.mountpoints.split(&keys).remove(&index) {
+                for (_, key) in keys.below(parent).values() {
+                    fetched.append_shared_key(key.clone());
+                }
+            }
+        }
+
+        returned.cut(parent);
+
+        for (_, key) in fetched.values() {
+            returned.append_shared_key(key.clone());
+        }
+
+        for (index, content) in backends.into_iter().zip(contents) {
+            self.mountpoints.backend_mut(index).set_last_read(content);
+        }
+
+        if !covered {
+            self.parents.push(parent.clone());
+        }
+
+        Ok(true)
+    }
+
+    fn set_keys(&mut self, ks: &KeySet, parent: &KeyName) -> Result<bool, KdbError> {
+        if !self.is_covered(parent) {
+            return Err(KdbError::Interface(format!("kdbGet has to be called for {} before kdbSet", parent)));
+        }
+
+        let backends = self.mountpoints.involved(parent);
+
+        for &index in &backends {
+            let backend = self.mountpoints.backend(index);
+            let content = backend.read()?;
+
+            if backend.last_read() != Some(&content) {
+                return Err(KdbError::ConflictingState(format!("{} was changed since the last kdbGet", backend.path().display())));
+            }
+        }
+
+        let changed: KeySet = ks.below(parent)
+            .values()
+            .map(|(_, key)| key.borrow().clone())
+            .filter(|key| PERSISTENT_NAMESPACES.contains(&key.namespace()))
+            .collect();
+        let mut changed = self.mountpoints.split(&changed);
+
+        let mut prepared = Vec::new();
+
+        for index in backends {
+            let changed = changed.remove(&index).unwrap_or_default();
+
+            match self.prepare(index, parent, changed) {
+                Ok(Some((keys, content))) => prepared.push((index, keys, content)),
+                Ok(None) => (),
+                Err(error) => {
+                    self.abort(&mut prepared);
+                    return Err(error);
+                }
+            }
+        }
+
+        let updated = !prepared.is_empty();
+        let mut committed = Vec::new();
+
+        while !prepared.is_empty() {
+            let (index, mut keys, content) = prepared.remove(0);
+            let backend = self.mountpoints.backend_mut(index);
+            let path = backend.path().display().to_string();
+
+            if let Err(error) = backend.commit(&mut keys, content) {
+                self.abort(&mut prepared);
+
+                if committed.is_empty() {
+                    return Err(error);
+                }
+
+                return Err(KdbError::PartialCommit(format!("{} stored the keys already, then storing failed: {}", committed.join(", "), error)));
+            }
+
+            committed.push(path);
+        }
+
+        Ok(updated)
+    }
+
+    /// Writes the keys of backend `index` with the keys below `parent`
+    /// replaced by `changed`. Returns the keys and the new content, or
+    /// `None` if the keys did not change.
+    fn prepare(&mut self, index: usize, parent: &KeyName, changed: KeySet) -> Result<Option<(KeySet, Vec<u8>)>, KdbError> {
+        let stored = self.mountpoints.backend_mut(index).load()?;
+
+        let mut keys = self.mountpoints
+            .split(&stored)
+            .remove(&index)
+            .unwrap_or_default();
+
+        if is_same(&keys.cut(parent), &changed) {
+            return Ok(None);
+        }
+
+        for (_, key) in changed.values() {
+            keys.append_shared_key(key.clone());
+        }
+
+        let content = self.mountpoints.backend_mut(index).prepare(&mut keys)?;
+
+        Ok(Some((keys, content)))
+    }
+
+    fn abort(&mut self, prepared: &mut [(usize, KeySet, Vec<u8>)]) {
+        for (index, keys, _) in prepared {
+            self.mountpoints.backend_mut(*index).abort(keys);
+        }
+    }
+
+    fn is_covered(&self, name: &KeyName) -> bool {
+        self.parents.iter().any(|parent| {
+            (parent.namespace() == KeyNamespace::Cascading || parent.namespace() == name.namespace())
+                && name.parts().starts_with(parent.parts())
+        })
+    }
+}
+
+fn is_same(left: &KeySet, right: &KeySet) -> bool {
+    left.size() == right.size()
+        && left.values().zip(right.values()).all(|((left_name, left), (right_name, right))| {
+            let (left, right) = (left.borrow(), right.borrow());
+
+            left_name == right_name
+                && left.value() == right.value()
+                && is_same(left.meta_keys(), right.meta_keys())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::Backend;
+    use crate::ecf::Ecf;
+    use crate::key::{KeyBuilder, KeyValue};
+    use crate::mount::MOUNTPOINTS;
+    use crate::plugin::{self, Plugin};
+    use std::fs;
+    use std::path::Path;
+
+    fn storage(name: &str) -> PathBuf {
+        let path = std::env::temp_dir()
+            .join(format!("elektra-rust-{}-{}", std::process::id(), name))
+            .join("storage.ecf");
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn open(path: &Path) -> Kdb {
+        let mut contract = KeySet::default();
+        let mut key = Key::from_str(STORAGE_CONTRACT).unwrap();
+        key.set_string(path.to_str().unwrap()).unwrap();
+        contract.append_key(key);
+
+        Kdb::open(&contract, &mut Key::from_str("/").unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_kdb_get_set() {
+        let path = storage("get-set");
+        let mut parent = Key::from_str("user:/app").unwrap();
+
+        let mut kdb = open(&path);
+        let mut ks = KeySet::default();
+        assert_eq!(kdb.get(&mut ks, &mut parent), Ok(true));
+        assert_eq!(ks.size(), 0);
+
+        let mut key = KeyBuilder::from_str("user:/app/port")
+            .unwrap()
+            .value(KeyValue::String(b"8080".to_vec()))
+            .meta("type", "long")
+            .build()
+            .unwrap();
+        ks.append_key(key.clone());
+        ks.append_key(KeyBuilder::from_str("user:/app/data\\/x").unwrap().value(KeyValue::Binary(vec![0, b'\n', 255])).build().unwrap());
+        ks.append_key(Key::from_str("user:/other").unwrap());
+
+        assert_eq!(kdb.set(&mut ks, &mut parent), Ok(true));
+        assert_eq!(kdb.set(&mut ks, &mut parent), Ok(false));
+        assert_eq!(kdb.get(&mut ks, &mut parent), Ok(false));
+        kdb.close(&mut parent).unwrap();
+
+        let mut kdb = open(&path);
+        let mut ks = KeySet::default();
+        assert_eq!(kdb.get(&mut ks, &mut parent), Ok(true));
+        assert_eq!(ks.size(), 2);
+
+        let port = ks.get(&KeyName::from_str("user:/app/port").unwrap()).unwrap();
+        assert_eq!(port.string(), Some("8080"));
+        assert_eq!(port.meta("type").unwrap().string(), Some("long"));
+        drop(port);
+
+        let data = ks.get(&KeyName::from_str("user:/app/data\\/x").unwrap()).unwrap();
+        assert_eq!(data.binary(), Some(&[0, b'\n', 255][..]));
+        drop(data);
+
+        key.set_string("9090").unwrap();
+        ks.append_key(key);
+        assert_eq!(kdb.set(&mut ks, &mut parent), Ok(true));
+    }
+
+    #[test]
+    fn test_kdb_set_errors() {
+        let path = storage("errors");
+        let mut parent = Key::from_str("user:/app").unwrap();
+
+        let mut kdb = open(&path);
+        let mut ks: KeySet = vec![Key::from_str("user:/app/a").unwrap()].into_iter().collect();
+
+        assert!(matches!(kdb.set(&mut ks, &mut parent), Err(KdbError::Interface(_))));
+        assert_eq!(parent.meta("error/number").unwrap().string(), Some("C01320"));
+        assert!(matches!(KdbError::from_key(&parent), Some(KdbError::Interface(_))));
+
+        let mut other = open(&path);
+        let mut other_ks = KeySet::default();
+        other.get(&mut other_ks, &mut parent).unwrap();
+        kdb.get(&mut KeySet::default(), &mut parent).unwrap();
+
+        assert_eq!(kdb.set(&mut ks, &mut parent), Ok(true));
+
+        other_ks.append_key(Key::from_str("user:/app/b").unwrap());
+        assert!(matches!(other.set(&mut other_ks, &mut parent), Err(KdbError::ConflictingState(_))));
+
+        assert_eq!(other.get(&mut other_ks, &mut parent), Ok(true));
+        assert_eq!(other_ks.size(), 1);
+    }
+
+    #[test]
+    fn test_kdb_mountpoints() {
+        let path = storage("mountpoints");
+        let directory = path.parent().unwrap();
+        let _ = fs::remove_file(directory.join("app.ecf"));
+        let _ = fs::remove_file(directory.join("hosts.ecf"));
+
+        let mut root = Key::from_str("/").unwrap();
+        let mut kdb = open(&path);
+        let mut ks = KeySet::default();
+        kdb.get(&mut ks, &mut root).unwrap();
+
+        for (mountpoint, file) in [("\\/app", "app.ecf"), ("user:\\/app\\/hosts", "hosts.ecf")] {
+            let mut key = Key::from_str(&format!("{}/{}/path", MOUNTPOINTS, mountpoint)).unwrap();
+            key.set_string(file).unwrap();
+            ks.append_key(key);
+        }
+
+        assert_eq!(kdb.set(&mut ks, &mut root), Ok(true));
+        kdb.close(&mut root).unwrap();
+
+        let mut kdb = open(&path);
+        assert_eq!(kdb.mountpoints().backends().len(), 3);
+
+        let mut ks: KeySet = ["user:/app/a", "user:/app/hosts/b", "system:/app/c", "user:/d", "proc:/app/e"]
+            .iter()
+            .map(|name| Key::from_str(name).unwrap())
+            .collect();
+
+        let mut parent = Key::from_str("/app").unwrap();
+        assert_eq!(kdb.get(&mut ks, &mut parent), Ok(true));
+        assert_eq!(ks.size(), 1);
+
+        for name in ["user:/app/a", "user:/app/hosts/b", "system:/app/c", "user:/d", "proc:/app/e"] {
+            ks.append_key(Key::from_str(name).unwrap());
+        }
+
+        assert_eq!(kdb.set(&mut ks, &mut parent), Ok(true));
+
+        let stored = |file: &str| {
+            let storage = plugin::open("ecf", KeySet::default(), &mut Key::from_str("/").unwrap()).unwrap();
+            let mut backend = Backend::new(KeyName::from_str("/").unwrap(), directory.join(file), storage);
+            let keys = backend.load().unwrap();
+            keys.values().map(|(name, _)| name.to_string()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(stored("app.ecf"), vec!["user:/app/a", "system:/app/c"]);
+        assert_eq!(stored("hosts.ecf"), vec!["user:/app/hosts/b"]);
+        assert!(!stored("storage.ecf").contains(&"user:/d".to_string()));
+
+        let mut hosts = Key::from_str("user:/app/hosts").unwrap();
+        ks.remove(&KeyName::from_str("user:/app/hosts/b").unwrap());
+        assert_eq!(kdb.set(&mut ks, &mut hosts), Ok(true));
+        assert_eq!(stored("hosts.ecf"), Vec::<String>::new());
+        assert_eq!(stored("app.ecf"), vec!["user:/app/a", "system:/app/c"]);
+    }
+
+    /// The ecf storage, failing to commit.
+    struct FailingCommit;
+
+    impl Plugin for FailingCommit {
+        fn name(&self) -> &str {
+            "failing-commit"
+        }
+
+        fn get(&mut self, returned: &mut KeySet, parent: &mut Key) -> Result<bool, KdbError> {
+            Ecf.get(returned, parent)
+        }
+
+        fn set(&mut self, returned: &mut KeySet, parent: &mut Key) -> Result<bool, KdbError> {
+            Ecf.set(returned, parent)
+        }
+
+        fn commit(&mut self, _returned: &mut KeySet, _parent: &mut Key) -> Result<bool, KdbError> {
+            Err(KdbError::Resource("commit failed".to_string()))
+        }
+    }
+
+    #[test]
+    fn test_kdb_partial_commit() {
+        plugin::register("failing-commit", || Box::new(FailingCommit)).unwrap();
+
+        let path = storage("partial-commit");
+        let directory = path.parent().unwrap();
+        let _ = fs::remove_file(directory.join("a.ecf"));
+        let _ = fs::remove_file(directory.join("b.ecf"));
+
+        let mut root = Key::from_str("/").unwrap();
+        let mut kdb = open(&path);
+        let mut ks = KeySet::default();
+        kdb.get(&mut ks, &mut root).unwrap();
+
+        for (mountpoint, file, storage) in [("\\/a", "a.ecf", "ecf"), ("\\/b", "b.ecf", "failing-commit")] {
+            for (setting, value) in [("path", file), ("plugin", storage)] {
+                let mut key = Key::from_str(&format!("{}/{}/{}", MOUNTPOINTS, mountpoint, setting)).unwrap();
+                key.set_string(value).unwrap();
+                ks.append_key(key);
+            }
+        }
+
+        assert_eq!(kdb.set(&mut ks, &mut root), Ok(true));
+        kdb.close(&mut root).unwrap();
+
+        let mut kdb = open(&path);
+        let mut ks = KeySet::default();
+        kdb.get(&mut ks, &mut root).unwrap();
+        ks.append_key(Key::from_str("user:/a/x").unwrap());
+        ks.append_key(Key::from_str("user:/b/x").unwrap());
+
+        let error = kdb.set(&mut ks, &mut root).unwrap_err();
+        assert!(matches!(&error, KdbError::PartialCommit(reason) if reason.contains("a.ecf") && !reason.contains("b.ecf")));
+        assert_eq!(KdbError::from_key(&root).map(|error| error.number()), Some("C01100"));
+    }
+}
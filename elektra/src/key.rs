@@ -1,130 +1,558 @@
+use std::cell::{Cell, Ref, RefCell, RefMut};
 use std::cmp::Ordering;
-use std::collections::BTreeMap;
+use std::collections::{btree_map, BTreeMap, VecDeque};
+use std::ops::Bound;
+use std::rc::Rc;
+use std::fmt;
 use std::iter::FromIterator;
 use std::str::FromStr;
 
-use relative_path::{RelativePath, RelativePathBuf};
+use bitflags::bitflags;
 
-#[derive(Copy, Clone)]
+/// The namespaces of Elektra, with the same numeric values as in libelektra.
+///
+/// The declaration order is also the order in which keys of different
+/// namespaces are sorted.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum KeyNamespace {
-    None,
-    Cascading,
-    Meta,
-    Spec,
-    Proc,
-    Dir,
-    User,
-    System,
-    Default,
+    None = 0,
+    Cascading = 1,
+    Meta = 2,
+    Spec = 3,
+    Proc = 4,
+    Dir = 5,
+    User = 6,
+    System = 7,
+    Default = 8,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum KeyNamespaceError {
     InvalidNamespaceError
 }
 
+impl KeyNamespace {
+    /// Whether names in this namespace carry a `<namespace>:` prefix.
+    /// Cascading names start with `/` directly and `None` has no names.
+    pub fn has_prefix(&self) -> bool {
+        !matches!(self, KeyNamespace::None | KeyNamespace::Cascading)
+    }
+
+    fn next(self) -> Option<KeyNamespace> {
+        match self {
+            KeyNamespace::None => Some(KeyNamespace::Cascading),
+            KeyNamespace::Cascading => Some(KeyNamespace::Meta),
+            KeyNamespace::Meta => Some(KeyNamespace::Spec),
+            KeyNamespace::Spec => Some(KeyNamespace::Proc),
+            KeyNamespace::Proc => Some(KeyNamespace::Dir),
+            KeyNamespace::Dir => Some(KeyNamespace::User),
+            KeyNamespace::User => Some(KeyNamespace::System),
+            KeyNamespace::System => Some(KeyNamespace::Default),
+            KeyNamespace::Default => None,
+        }
+    }
+}
+
 impl FromStr for KeyNamespace {
     type Err = KeyNamespaceError;
 
     fn from_str(namespace: &str) -> Result<Self, Self::Err> {
         match namespace {
+            "none" => Ok(KeyNamespace::None),
+            "cascading" => Ok(KeyNamespace::Cascading),
             "meta" => Ok(KeyNamespace::Meta),
+            "spec" => Ok(KeyNamespace::Spec),
+            "proc" => Ok(KeyNamespace::Proc),
+            "dir" => Ok(KeyNamespace::Dir),
             "user" => Ok(KeyNamespace::User),
-            _ => Ok(KeyNamespace::Default),
+            "system" => Ok(KeyNamespace::System),
+            "default" => Ok(KeyNamespace::Default),
+            _ => Err(KeyNamespaceError::InvalidNamespaceError),
         }
     }
 }
 
-impl ToString for KeyNamespace {
-    fn to_string(&self) -> String {
+impl fmt::Display for KeyNamespace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let namespace = match self {
+            KeyNamespace::None => "none",
+            KeyNamespace::Cascading => "cascading",
+            KeyNamespace::Meta => "meta",
+            KeyNamespace::Spec => "spec",
+            KeyNamespace::Proc => "proc",
+            KeyNamespace::Dir => "dir",
             KeyNamespace::User => "user",
-            _ => "test",
+            KeyNamespace::System => "system",
+            KeyNamespace::Default => "default",
         };
 
-        namespace.to_string()
+        f.write_str(namespace)
     }
 }
 
+/// A key name, stored as its namespace plus the list of unescaped parts.
+///
+/// The canonical escaped form (e.g. `user:/a/b\/c`) is produced on demand
+/// by the `Display` implementation and by [`KeyName::escaped`].
+///
+/// Names are ordered by namespace first and then part by part, comparing
+/// the unescaped bytes, which is the order libelektra uses.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct KeyName {
     namespace: KeyNamespace,
-    pub path: RelativePathBuf,
+    parts: Vec<Vec<u8>>,
 }
 
 impl KeyName {
-    pub fn new (namespace: KeyNamespace, path: RelativePathBuf) -> KeyName {
+    pub fn new(namespace: KeyNamespace, parts: Vec<Vec<u8>>) -> KeyName {
         KeyName {
             namespace,
-            path
+            parts,
+        }
+    }
+
+    /// Parses an escaped key name, canonicalizing it along the way.
+    ///
+    /// Elektra key names are byte strings, so this does not require the
+    /// name to be valid UTF-8.
+    pub fn from_bytes(name: &[u8]) -> Result<KeyName, KeyError> {
+        if name.is_empty() {
+            return Err(KeyError::InvalidNameError(KeyNameError::EmptyName));
+        }
+
+        let (namespace, path) = split_namespace(name)?;
+
+        let mut key_name = KeyName::new(namespace, Vec::new());
+        parse_parts(path, &mut key_name.parts)?;
+        key_name.validate()?;
+
+        Ok(key_name)
+    }
+
+    pub fn namespace(&self) -> KeyNamespace {
+        self.namespace
+    }
+
+    /// Moves the name to another namespace, keeping its parts.
+    ///
+    /// On error the name is left unchanged.
+    pub fn set_namespace(&mut self, namespace: KeyNamespace) -> Result<(), KeyError> {
+        let previous = std::mem::replace(&mut self.namespace, namespace);
+
+        if let Err(error) = self.validate() {
+            self.namespace = previous;
+            return Err(error);
+        }
+
+        Ok(())
+    }
+
+    /// Checks the rules that depend on the namespace: there are no names
+    /// without a namespace, and the first part of a meta key must not look
+    /// like another namespace (as in `meta:/user:/a`).
+    fn validate(&self) -> Result<(), KeyError> {
+        match self.namespace {
+            KeyNamespace::None => {
+                Err(KeyError::InvalidNameError(KeyNameError::InvalidNamespace))
+            }
+            KeyNamespace::Meta => match self.parts.first() {
+                Some(part) if is_namespace_prefix(part) => {
+                    Err(KeyError::InvalidNameError(KeyNameError::NestedNamespace))
+                }
+                _ => Ok(()),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// The unescaped parts of this name, without the namespace.
+    pub fn parts(&self) -> &[Vec<u8>] {
+        &self.parts
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.parts.is_empty()
+    }
+
+    /// The last unescaped part, or `None` for a root key.
+    pub fn base_name(&self) -> Option<&[u8]> {
+        self.parts.last().map(Vec::as_slice)
+    }
+
+    /// Appends an escaped, relative key name such as `a/b\/c/../d`.
+    ///
+    /// On error the name is left unchanged.
+    pub fn append_name(&mut self, name: &[u8]) -> Result<(), KeyError> {
+        let mut parts = self.parts.clone();
+        parse_parts(name, &mut parts)?;
+
+        let previous = std::mem::replace(&mut self.parts, parts);
+
+        if let Err(error) = self.validate() {
+            self.parts = previous;
+            return Err(error);
+        }
+
+        Ok(())
+    }
+
+    /// Appends a single unescaped part, which is taken literally.
+    pub fn append_part(&mut self, part: &[u8]) {
+        self.parts.push(part.to_vec());
+    }
+
+    /// Whether this name is below `parent` in the key hierarchy.
+    ///
+    /// Namespaces have to match, unless one of the names is cascading.
+    pub fn is_below(&self, parent: &KeyName) -> bool {
+        self.parts.len() > parent.parts.len() && self.is_below_or_same(parent)
+    }
+
+    /// Like [`KeyName::is_below`], but also true for the same name.
+    pub fn is_below_or_same(&self, parent: &KeyName) -> bool {
+        self.is_in_namespace_of(parent) && self.parts.starts_with(&parent.parts)
+    }
+
+    /// Whether this name is a direct child of `parent`.
+    pub fn is_directly_below(&self, parent: &KeyName) -> bool {
+        self.parts.len() == parent.parts.len() + 1 && self.is_below_or_same(parent)
+    }
+
+    /// The smallest name that sorts after this name and everything below
+    /// it in the same namespace, or `None` if there is no such name.
+    fn subtree_end(&self) -> Option<KeyName> {
+        match self.parts.split_last() {
+            Some((last, init)) => {
+                let mut last = last.clone();
+                last.push(0);
+
+                let mut parts = init.to_vec();
+                parts.push(last);
+
+                Some(KeyName::new(self.namespace, parts))
+            }
+            None => self.namespace.next().map(|namespace| KeyName::new(namespace, vec![])),
+        }
+    }
+
+    fn is_in_namespace_of(&self, other: &KeyName) -> bool {
+        self.namespace == other.namespace
+            || self.namespace == KeyNamespace::Cascading
+            || other.namespace == KeyNamespace::Cascading
+    }
+
+    /// The canonical escaped form of this name.
+    pub fn escaped(&self) -> Vec<u8> {
+        let mut name = Vec::new();
+
+        if self.namespace.has_prefix() {
+            name.extend_from_slice(self.namespace.to_string().as_bytes());
+            name.push(b':');
+        }
+
+        if self.parts.is_empty() {
+            name.push(b'/');
+        }
+
+        for part in &self.parts {
+            name.push(b'/');
+            escape_part(part, &mut name);
+        }
+
+        name
+    }
+
+    /// The unescaped form of this name as used by libelektra: the
+    /// namespace as a single byte, followed by every part terminated by a
+    /// NUL byte. The root key is encoded as an empty part.
+    pub fn unescaped(&self) -> Vec<u8> {
+        let mut name = vec![self.namespace as u8, 0];
+
+        if self.parts.is_empty() {
+            name.push(0);
         }
+
+        for part in &self.parts {
+            name.extend_from_slice(part);
+            name.push(0);
+        }
+
+        name
     }
+}
+
+/// Splits an escaped name into its namespace and the path starting at the
+/// first `/`.
+fn split_namespace(name: &[u8]) -> Result<(KeyNamespace, &[u8]), KeyError> {
+    if name[0] == b'/' {
+        return Ok((KeyNamespace::Cascading, name));
+    }
+
+    let slash = name.iter().position(|&c| c == b'/').unwrap_or(name.len());
 
-    pub fn set_namespace(&mut self, namespace: KeyNamespace) {
-        self.namespace = namespace
+    let colon = name[..slash].iter().position(|&c| c == b':')
+        .ok_or(KeyError::InvalidNameError(KeyNameError::MissingLeadingSlash))?;
+
+    let namespace = std::str::from_utf8(&name[..colon]).ok()
+        .and_then(|namespace| KeyNamespace::from_str(namespace).ok())
+        .filter(KeyNamespace::has_prefix)
+        .ok_or(KeyError::InvalidNameError(KeyNameError::UnknownNamespace))?;
+
+    // the namespace has to be followed by a `/`, also for the root key
+    if colon + 1 != slash || slash == name.len() {
+        return Err(KeyError::InvalidNameError(KeyNameError::MissingLeadingSlash));
     }
+
+    Ok((namespace, &name[slash..]))
 }
 
-impl FromStr for KeyName {
-    type Err = KeyError;
+/// Whether an unescaped part has the form `<namespace>:`.
+fn is_namespace_prefix(part: &[u8]) -> bool {
+    match part.split_last() {
+        Some((b':', namespace)) => std::str::from_utf8(namespace).ok()
+            .is_some_and(|namespace| KeyNamespace::from_str(namespace).is_ok()),
+        _ => false,
+    }
+}
 
-    fn from_str(name: &str) -> Result<Self, Self::Err> {
-        let mut splitter = name.splitn(2, ":");
+/// Parses the escaped parts of `path` and applies them to `parts`, so that
+/// `..` can also remove parts that were already present.
+fn parse_parts(path: &[u8], parts: &mut Vec<Vec<u8>>) -> Result<(), KeyError> {
+    let mut start = 0;
+    let mut i = 0;
 
-        let namespace = splitter.next()
-            .ok_or(KeyError::InvalidNameError)?;
+    while i <= path.len() {
+        if i == path.len() || path[i] == b'/' {
+            apply_part(&path[start..i], parts)?;
+            start = i + 1;
+        } else if path[i] == b'\\' {
+            if i + 1 == path.len() {
+                return Err(KeyError::InvalidNameError(KeyNameError::DanglingEscape));
+            }
 
-        let path = splitter.next()
-            .ok_or(KeyError::InvalidNameError)?;
+            i += 1;
+        }
 
-        let key_namespace = KeyNamespace::from_str(namespace)
-            .or(Err(KeyError::InvalidNameError))?;
+        i += 1;
+    }
 
-        Ok(KeyName {
-                namespace: key_namespace,
-                path: RelativePathBuf::from(path).normalize(),
-        })
+    Ok(())
+}
+
+fn apply_part(raw: &[u8], parts: &mut Vec<Vec<u8>>) -> Result<(), KeyError> {
+    match raw {
+        b"" | b"." => {}
+        b".." => {
+            parts.pop()
+                .ok_or(KeyError::InvalidNameError(KeyNameError::AboveRoot))?;
+        }
+        b"%" => parts.push(Vec::new()),
+        b"\\%" | b"\\." | b"\\.." => parts.push(raw[1..].to_vec()),
+        _ if raw[0] == b'#' => match array_part(raw) {
+            ArrayPart::NotArray | ArrayPart::Canonical => parts.push(unescape(raw)?),
+            ArrayPart::NonCanonical => {
+                let mut part = vec![b'#'];
+                part.resize(raw.len() - 1, b'_');
+                part.extend_from_slice(&raw[1..]);
+                parts.push(part);
+            }
+            ArrayPart::Invalid => {
+                return Err(KeyError::InvalidNameError(KeyNameError::InvalidArrayPart));
+            }
+        },
+        _ if raw.starts_with(b"\\#") => match array_part(&raw[1..]) {
+            ArrayPart::NotArray => {
+                return Err(KeyError::InvalidNameError(KeyNameError::InvalidEscapeSequence));
+            }
+            _ => parts.push(raw[1..].to_vec()),
+        },
+        _ => parts.push(unescape(raw)?),
     }
+
+    Ok(())
 }
 
-impl ToString for KeyName {
-    fn to_string(&self) -> String {
-        let mut name = self.namespace.to_string();
-        name.push_str(":/");
-        name.push_str(self.path.as_str());
+/// Resolves `\\` and `\/` inside a part; any other escape is invalid here.
+fn unescape(raw: &[u8]) -> Result<Vec<u8>, KeyError> {
+    let mut part = Vec::with_capacity(raw.len());
+    let mut chars = raw.iter();
 
-        name
+    while let Some(&c) = chars.next() {
+        if c == b'\\' {
+            match chars.next() {
+                Some(&escaped) if escaped == b'\\' || escaped == b'/' => part.push(escaped),
+                _ => return Err(KeyError::InvalidNameError(KeyNameError::InvalidEscapeSequence)),
+            }
+        } else {
+            part.push(c);
+        }
+    }
+
+    Ok(part)
+}
+
+fn escape_part(part: &[u8], name: &mut Vec<u8>) {
+    match part {
+        b"" => {
+            name.push(b'%');
+            return;
+        }
+        b"%" | b"." | b".." => name.push(b'\\'),
+        _ if part[0] == b'#' => match array_part(part) {
+            ArrayPart::NotArray | ArrayPart::Canonical => {}
+            ArrayPart::NonCanonical | ArrayPart::Invalid => name.push(b'\\'),
+        },
+        _ => {}
+    }
+
+    for &c in part {
+        if c == b'\\' || c == b'/' {
+            name.push(b'\\');
+        }
+
+        name.push(c);
+    }
+}
+
+enum ArrayPart {
+    NotArray,
+    Canonical,
+    NonCanonical,
+    Invalid,
+}
+
+/// Classifies parts of the form `#`, followed by underscores and digits.
+///
+/// Canonical array parts carry one underscore less than they have digits
+/// (`#5`, `#_10`, `#__100`). Plain digits (`#10`) are accepted and
+/// canonicalized, anything else of that shape is rejected.
+fn array_part(part: &[u8]) -> ArrayPart {
+    let underscores = part[1..].iter().take_while(|&&c| c == b'_').count();
+    let digits = &part[1 + underscores..];
+
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return ArrayPart::NotArray;
+    }
+
+    let in_range = std::str::from_utf8(digits).ok()
+        .and_then(|digits| digits.parse::<i64>().ok())
+        .is_some();
+
+    if !in_range || (digits.len() > 1 && digits[0] == b'0') {
+        ArrayPart::Invalid
+    } else if underscores == digits.len() - 1 {
+        ArrayPart::Canonical
+    } else if underscores == 0 {
+        ArrayPart::NonCanonical
+    } else {
+        ArrayPart::Invalid
+    }
+}
+
+impl FromStr for KeyName {
+    type Err = KeyError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        KeyName::from_bytes(name.as_bytes())
+    }
+}
+
+impl fmt::Display for KeyName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&String::from_utf8_lossy(&self.escaped()))
     }
 }
 
+/// The reason a key name was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyNameError {
+    EmptyName,
+    MissingLeadingSlash,
+    UnknownNamespace,
+    InvalidNamespace,
+    NestedNamespace,
+    InvalidEscapeSequence,
+    DanglingEscape,
+    AboveRoot,
+    InvalidArrayPart,
+}
+
 #[derive(Debug)]
 pub enum KeyError {
-    InvalidNameError,
+    InvalidNameError(KeyNameError),
     NullPointerError,
+    LockedError,
+    TypeMismatchError,
+}
+
+/// The value of a key.
+///
+/// Strings are byte strings without a terminating NUL, like in libelektra
+/// they are not required to be valid UTF-8.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KeyValue {
+    String(Vec<u8>),
+    Binary(Vec<u8>),
+}
+
+impl KeyValue {
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            KeyValue::String(value) | KeyValue::Binary(value) => value,
+        }
+    }
+}
+
+bitflags! {
+    /// The parts of a key that can be locked, with libelektra's values.
+    pub struct KeyLockFlags: u32 {
+        const KEY_LOCK_NAME = 1 << 17;
+        const KEY_LOCK_VALUE = 1 << 18;
+        const KEY_LOCK_META = 1 << 19;
+    }
 }
 
-type KeyValue = Vec<u8>;
+bitflags! {
+    /// The parts of a key copied by [`Key::copy_from`], with libelektra's values.
+    pub struct KeyCopyFlags: u32 {
+        const KEY_CP_NAME = 1 << 0;
+        const KEY_CP_STRING = 1 << 1;
+        const KEY_CP_VALUE = 1 << 2;
+        const KEY_CP_META = 1 << 3;
+        const KEY_CP_ALL = Self::KEY_CP_NAME.bits | Self::KEY_CP_VALUE.bits | Self::KEY_CP_META.bits;
+    }
+}
 
+/// The meta key that marks a key as binary.
+const BINARY_META: &str = "binary";
+
+#[derive(Clone, Debug)]
 pub struct Key {
     name: KeyName,
-    value: Option<KeyValue>
+    value: Option<KeyValue>,
+    meta: KeySet,
+    // a `Cell`, so that keys shared with other borrowers can get their name
+    // locked when they are added to a `KeySet`
+    locks: Cell<KeyLockFlags>,
 }
 
 impl Eq for Key {}
 
 impl PartialEq<Self> for Key {
     fn eq(&self, other: &Self) -> bool {
-        self.name.path == other.name.path
+        self.name == other.name
     }
 }
 
 impl PartialOrd<Self> for Key {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.name.path.cmp(&other.name.path))
+        Some(self.cmp(other))
     }
 }
 
 impl Ord for Key {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.name.path.cmp(&other.name.path)
+        self.name.cmp(&other.name)
     }
 }
 
@@ -132,7 +560,9 @@ impl Key {
     pub fn new(key_name: KeyName) -> Key {
         Key {
             name: key_name,
-            value: None
+            value: None,
+            meta: KeySet::default(),
+            locks: Cell::new(KeyLockFlags::empty()),
         }
     }
 
@@ -140,29 +570,291 @@ impl Key {
         self.name.to_string()
     }
 
-    pub fn set_name(&mut self, name: KeyName) {
+    pub fn key_name(&self) -> &KeyName {
+        &self.name
+    }
+
+    pub fn set_name(&mut self, name: KeyName) -> Result<(), KeyError> {
+        self.check_lock(KeyLockFlags::KEY_LOCK_NAME)?;
+
         self.name = name;
+        Ok(())
+    }
+
+    /// Locks parts of the key against modification. Locks cannot be
+    /// removed again; keys get their name locked when added to a `KeySet`.
+    pub fn lock(&mut self, what: KeyLockFlags) {
+        self.lock_shared(what);
+    }
+
+    fn lock_shared(&self, what: KeyLockFlags) {
+        self.locks.set(self.locks.get() | what);
+    }
+
+    /// Whether any of the parts in `what` is locked.
+    pub fn is_locked(&self, what: KeyLockFlags) -> bool {
+        self.locks.get().intersects(what)
+    }
+
+    pub fn locks(&self) -> KeyLockFlags {
+        self.locks.get()
+    }
+
+    fn check_lock(&self, what: KeyLockFlags) -> Result<(), KeyError> {
+        if self.is_locked(what) {
+            return Err(KeyError::LockedError);
+        }
+
+        Ok(())
     }
 
-    pub fn set_value(&mut self, value: KeyValue) {
+    /// Sets the value, keeping the `binary` meta key in sync with its type.
+    ///
+    /// The `binary` meta key is updated even if the metadata is locked.
+    pub fn set_value(&mut self, value: KeyValue) -> Result<(), KeyError> {
+        self.check_lock(KeyLockFlags::KEY_LOCK_VALUE)?;
+
+        let binary_meta = meta_name(BINARY_META.as_bytes())
+            .expect("binary is a valid meta name");
+
+        match value {
+            KeyValue::String(_) => {
+                self.meta.keys.remove(&binary_meta);
+            }
+            KeyValue::Binary(_) => self.insert_meta(binary_meta, b""),
+        }
+
         self.value = Some(value);
+        Ok(())
+    }
+
+    /// The string value, which is empty for keys without a value.
+    ///
+    /// Returns `None` for binary keys and for strings that are not UTF-8.
+    pub fn string(&self) -> Option<&str> {
+        if self.is_binary() {
+            return None;
+        }
+
+        match &self.value {
+            Some(value) => std::str::from_utf8(value.as_bytes()).ok(),
+            None => Some(""),
+        }
+    }
+
+    pub fn set_string(&mut self, value: &str) -> Result<(), KeyError> {
+        self.set_value(KeyValue::String(value.as_bytes().to_vec()))
+    }
+
+    /// The binary value, or `None` if this is not a binary key.
+    pub fn binary(&self) -> Option<&[u8]> {
+        if !self.is_binary() {
+            return None;
+        }
+
+        Some(self.value.as_ref().map_or(&[], KeyValue::as_bytes))
+    }
+
+    pub fn set_binary(&mut self, value: &[u8]) -> Result<(), KeyError> {
+        self.set_value(KeyValue::Binary(value.to_vec()))
+    }
+
+    /// Whether the key is marked as binary by its `binary` meta key.
+    pub fn is_binary(&self) -> bool {
+        self.meta(BINARY_META).is_some()
+    }
+
+    pub fn is_string(&self) -> bool {
+        !self.is_binary()
     }
 
-    pub fn append_name(&mut self, name: &str) {
-        self.name.path = self.name.path.join(RelativePath::new(name));
+    pub fn append_name<N: AsRef<[u8]>>(&mut self, name: N) -> Result<(), KeyError> {
+        self.check_lock(KeyLockFlags::KEY_LOCK_NAME)?;
+        self.name.append_name(name.as_ref())
+    }
+
+    /// Appends `base_name` as a single part, which is taken literally.
+    ///
+    /// Fails like [`KeyName::from_str`] if the result is not a valid name,
+    /// as `meta:/user:`. On error the name is left unchanged.
+    pub fn append_base_name(&mut self, base_name: &[u8]) -> Result<(), KeyError> {
+        self.check_lock(KeyLockFlags::KEY_LOCK_NAME)?;
+
+        let mut name = self.name.clone();
+        name.append_part(base_name);
+        name.validate()?;
+
+        self.name = name;
+        Ok(())
     }
 
     pub fn namespace(&self) -> KeyNamespace {
         self.name.namespace
     }
 
-    pub fn set_namespace(&mut self, namespace: KeyNamespace) {
-        self.name.set_namespace(namespace);
+    pub fn set_namespace(&mut self, namespace: KeyNamespace) -> Result<(), KeyError> {
+        self.check_lock(KeyLockFlags::KEY_LOCK_NAME)?;
+        self.name.set_namespace(namespace)
+    }
+
+    /// Whether this key is below `parent`, see [`KeyName::is_below`].
+    pub fn is_below(&self, parent: &Key) -> bool {
+        self.name.is_below(&parent.name)
+    }
+
+    pub fn is_below_or_same(&self, parent: &Key) -> bool {
+        self.name.is_below_or_same(&parent.name)
+    }
+
+    pub fn is_directly_below(&self, parent: &Key) -> bool {
+        self.name.is_directly_below(&parent.name)
     }
 
     pub fn value(&self) -> Option<&KeyValue> {
-        return self.value.as_ref()
+        self.value.as_ref()
+    }
+
+    /// Moves the key behind a shared reference, so it can be added to
+    /// several `KeySet`s.
+    pub fn into_shared(self) -> SharedKey {
+        Rc::new(RefCell::new(self))
+    }
+
+    /// The metadata of this key. All keys in it are in the meta namespace.
+    pub fn meta_keys(&self) -> &KeySet {
+        &self.meta
+    }
+
+    /// Looks up a meta key, given either as `type` or as `meta:/type`.
+    pub fn meta<N: AsRef<[u8]>>(&self, name: N) -> Option<Ref<'_, Key>> {
+        let name = meta_name(name.as_ref()).ok()?;
+        self.meta.keys.get(&name).map(|meta_key| meta_key.borrow())
+    }
+
+    /// Sets the meta key `name` to `value`, replacing an existing one. Like
+    /// in libelektra, names and values do not have to be UTF-8.
+    pub fn set_meta<N: AsRef<[u8]>, V: AsRef<[u8]>>(&mut self, name: N, value: V) -> Result<(), KeyError> {
+        self.check_lock(KeyLockFlags::KEY_LOCK_META)?;
+
+        self.insert_meta(meta_name(name.as_ref())?, value.as_ref());
+        Ok(())
+    }
+
+    fn insert_meta(&mut self, name: KeyName, value: &[u8]) {
+        let mut meta_key = Key::new(name);
+        meta_key.value = Some(KeyValue::String(value.to_vec()));
+
+        self.meta.append_key(meta_key);
+    }
+
+    pub fn remove_meta<N: AsRef<[u8]>>(&mut self, name: N) -> Result<Option<SharedKey>, KeyError> {
+        self.check_lock(KeyLockFlags::KEY_LOCK_META)?;
+
+        let name = meta_name(name.as_ref())?;
+        Ok(self.meta.keys.remove(&name))
+    }
+
+    /// Copies the meta key `name` from `source`. If `source` does not have
+    /// it, it is removed from this key as well.
+    pub fn copy_meta_from<N: AsRef<[u8]>>(&mut self, source: &Key, name: N) -> Result<(), KeyError> {
+        self.check_lock(KeyLockFlags::KEY_LOCK_META)?;
+
+        let name = meta_name(name.as_ref())?;
+
+        match source.meta.keys.get(&name) {
+            Some(meta_key) => self.meta.append_shared_key(meta_key.clone()),
+            None => {
+                self.meta.keys.remove(&name);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Copies the parts of `source` selected by `flags` into this key.
+    ///
+    /// `KEY_CP_STRING` copies the value only if `source` is not binary and
+    /// fails otherwise, `KEY_CP_VALUE` copies any value. `KEY_CP_META`
+    /// replaces all metadata. Nothing is copied if one of the selected parts
+    /// is locked in this key.
+    pub fn copy_from(&mut self, source: &Key, flags: KeyCopyFlags) -> Result<(), KeyError> {
+        let copy_value = flags.intersects(KeyCopyFlags::KEY_CP_STRING | KeyCopyFlags::KEY_CP_VALUE);
+
+        if flags.contains(KeyCopyFlags::KEY_CP_NAME) {
+            self.check_lock(KeyLockFlags::KEY_LOCK_NAME)?;
+        }
+
+        if copy_value {
+            self.check_lock(KeyLockFlags::KEY_LOCK_VALUE)?;
+        }
+
+        if flags.contains(KeyCopyFlags::KEY_CP_META) {
+            self.check_lock(KeyLockFlags::KEY_LOCK_META)?;
+        }
+
+        if flags.contains(KeyCopyFlags::KEY_CP_STRING)
+            && !flags.contains(KeyCopyFlags::KEY_CP_VALUE)
+            && source.is_binary()
+        {
+            return Err(KeyError::TypeMismatchError);
+        }
+
+        if flags.contains(KeyCopyFlags::KEY_CP_NAME) {
+            self.name = source.name.clone();
+        }
+
+        if flags.contains(KeyCopyFlags::KEY_CP_META) {
+            self.meta = source.meta.clone();
+        } else if copy_value {
+            let binary_meta = meta_name(BINARY_META.as_bytes())
+                .expect("binary is a valid meta name");
+
+            match source.meta.keys.get(&binary_meta) {
+                Some(meta_key) => self.meta.append_shared_key(meta_key.clone()),
+                None => {
+                    self.meta.keys.remove(&binary_meta);
+                }
+            }
+        }
+
+        if copy_value {
+            self.value = source.value.clone();
+        }
+
+        Ok(())
+    }
+
+    /// Copies all meta keys of `source`, keeping other meta keys of this key.
+    pub fn copy_all_meta_from(&mut self, source: &Key) -> Result<(), KeyError> {
+        self.check_lock(KeyLockFlags::KEY_LOCK_META)?;
+
+        for meta_key in source.meta.keys.values() {
+            self.meta.append_shared_key(meta_key.clone());
+        }
+
+        Ok(())
+    }
+}
+
+/// Resolves a meta key name, which may omit the `meta:/` prefix.
+fn meta_name(name: &[u8]) -> Result<KeyName, KeyError> {
+    let key_name = if name.starts_with(b"meta:/") {
+        KeyName::from_bytes(name)?
+    } else {
+        let mut key_name = KeyName::new(KeyNamespace::Meta, Vec::new());
+        key_name.append_name(name)?;
+        key_name
+    };
+
+    if key_name.namespace() != KeyNamespace::Meta {
+        return Err(KeyError::InvalidNameError(KeyNameError::InvalidNamespace));
+    }
+
+    if key_name.is_root() {
+        return Err(KeyError::InvalidNameError(KeyNameError::EmptyName));
     }
+
+    Ok(key_name)
 }
 
 impl FromStr for Key {
@@ -176,7 +868,10 @@ impl FromStr for Key {
 
 pub struct KeyBuilder {
     name: KeyName,
-    value: Option<KeyValue>
+    value: Option<KeyValue>,
+    meta: Vec<(Vec<u8>, Vec<u8>)>,
+    binary: bool,
+    locks: KeyLockFlags,
 }
 
 impl KeyBuilder {
@@ -184,6 +879,9 @@ impl KeyBuilder {
         KeyBuilder {
             name: key_name,
             value: None,
+            meta: Vec::new(),
+            binary: false,
+            locks: KeyLockFlags::empty(),
         }
     }
 
@@ -192,13 +890,39 @@ impl KeyBuilder {
         self
     }
 
+    pub fn meta<N: AsRef<[u8]>, V: AsRef<[u8]>>(mut self, name: N, value: V) -> KeyBuilder {
+        self.meta.push((name.as_ref().to_vec(), value.as_ref().to_vec()));
+        self
+    }
+
+    /// Makes the key binary, also if its value is given as a string.
+    pub fn binary(mut self) -> KeyBuilder {
+        self.binary = true;
+        self
+    }
+
+    /// Locks the given parts of the key once it is built.
+    pub fn lock(mut self, what: KeyLockFlags) -> KeyBuilder {
+        self.locks |= what;
+        self
+    }
+
     pub fn build(self) -> Result<Key, KeyError> {
         let mut key = Key::new(self.name);
 
-        if let Some(value) = self.value {
-            key.set_value(value);
+        for (name, value) in &self.meta {
+            key.set_meta(name, value)?;
+        }
+
+        match self.value {
+            Some(KeyValue::String(value)) if self.binary => key.set_value(KeyValue::Binary(value))?,
+            Some(value) => key.set_value(value)?,
+            None if self.binary => key.set_meta(BINARY_META, "")?,
+            None => {}
         }
 
+        key.lock(self.locks);
+
         Ok(key)
     }
 }
@@ -207,17 +931,51 @@ impl FromStr for KeyBuilder {
     type Err = KeyError;
 
     fn from_str(name: &str) -> Result<Self, Self::Err> {
-        let key_name = KeyName::from_str(name)?;
+        Ok(KeyBuilder::new(KeyName::from_str(name)?))
+    }
+}
 
-        Ok(KeyBuilder {
-            name: key_name,
-            value: None,
-        })
+/// A key that is shared between several owners, most commonly `KeySet`s.
+///
+/// Changes made through one owner are visible to all of them, and the key
+/// is dropped together with its last owner.
+pub type SharedKey = Rc<RefCell<Key>>;
+
+bitflags! {
+    /// Options for [`KeySet::lookup_with`], with libelektra's values.
+    pub struct KeyLookupFlags: u32 {
+        /// Only meaningful for the C API, where it deletes the search key.
+        const KDB_O_DEL = 1 << 0;
+        const KDB_O_POP = 1 << 1;
+        /// Only meaningful for the C API, where it only searches after the cursor.
+        const KDB_O_NOALL = 1 << 14;
+        const KDB_O_SPEC = 1 << 15;
+        const KDB_O_NOCASCADING = 1 << 17;
+        const KDB_O_NOSPEC = 1 << 18;
+        const KDB_O_NODEFAULT = 1 << 19;
     }
 }
 
+/// The namespaces searched by a cascading lookup after the spec namespace.
+const CASCADING_NAMESPACES: [KeyNamespace; 4] = [
+    KeyNamespace::Proc,
+    KeyNamespace::Dir,
+    KeyNamespace::User,
+    KeyNamespace::System,
+];
+
+/// A sorted set of keys.
+///
+/// Keys are stored as [`SharedKey`]s, so a key can be a member of several
+/// sets at once. Cloning a `KeySet` therefore does not copy its keys.
+///
+/// Sharing keys through `Rc<RefCell<Key>>` keeps this cheap, but makes
+/// `KeySet` neither `Send` nor `Sync`, and borrows of the keys are checked
+/// at runtime: a key must not be borrowed mutably, e.g. through
+/// [`KeySet::get_mut`], while it is added to or looked up in a set.
+#[derive(Clone, Debug, Default)]
 pub struct KeySet {
-    keys: BTreeMap<String, Key>
+    keys: BTreeMap<KeyName, SharedKey>
 }
 
 impl KeySet {
@@ -225,8 +983,22 @@ impl KeySet {
         self.keys.len()
     }
 
+    /// Adds a key, replacing a key with the same name. The name of the key
+    /// is locked, as changing it would break the order of the set.
     pub fn append_key(&mut self, key: Key) {
-        self.keys.insert(key.name().clone(), key);
+        self.append_shared_key(key.into_shared());
+    }
+
+    /// Adds a key that may also be a member of other sets, see
+    /// [`KeySet::append_key`].
+    pub fn append_shared_key(&mut self, key: SharedKey) {
+        let name = {
+            let key = key.borrow();
+            key.lock_shared(KeyLockFlags::KEY_LOCK_NAME);
+            key.name.clone()
+        };
+
+        self.keys.insert(name, key);
     }
 
     /*
@@ -238,20 +1010,429 @@ impl KeySet {
     }
     */
 
-    pub fn lookup(&mut self, name: String) -> Option<Key> {
-        self.keys.remove(&name)
+    /// Looks up a key without removing it from the set.
+    pub fn lookup(&self, name: &KeyName) -> Option<SharedKey> {
+        self.keys.get(name).cloned()
     }
 
-    pub fn values(&self) -> std::collections::btree_map::Iter<String, Key> {
-        self.keys.iter()
-    }
-}
+    /// Looks up `key` the way libelektra's `ksLookup` does, see
+    /// [`KeyLookup::resolve`]. With `KDB_O_POP` the found key is removed
+    /// from the set.
+    pub fn lookup_with(&mut self, key: &Key, options: KeyLookupFlags) -> Option<SharedKey> {
+        let found = self.resolve(key, options)?;
 
-impl Default for KeySet {
-    fn default() -> KeySet {
-        KeySet {
-            keys: BTreeMap::new()
+        if options.contains(KeyLookupFlags::KDB_O_POP) {
+            let name = found.borrow().key_name().clone();
+            self.remove(&name);
         }
+
+        Some(found)
+    }
+
+    /// Resolves the cascading `name` and returns the key found together
+    /// with the namespace it came from.
+    ///
+    /// If there is a spec key for `name`, the keys named by its
+    /// `override/#` metadata are tried first, then `name` in the namespaces
+    /// listed in `namespace/#`, then the keys named by `fallback/#`.
+    /// Without a spec key, without `namespace/#` or if the spec key leads to
+    /// no key, the namespaces proc, dir, user and system are searched. The
+    /// cascading key itself and the default namespace come last.
+    pub fn lookup_cascading(&self, name: &KeyName) -> Option<(SharedKey, KeyNamespace)> {
+        let name = in_namespace(name, KeyNamespace::Cascading);
+
+        lookup_by_cascading(self, &name, KeyLookupFlags::empty())
+            .map(|key| {
+                let namespace = key.borrow().namespace();
+                (key, namespace)
+            })
+    }
+
+    pub fn get(&self, name: &KeyName) -> Option<Ref<'_, Key>> {
+        self.keys.get(name).map(|key| key.borrow())
+    }
+
+    pub fn get_mut(&mut self, name: &KeyName) -> Option<RefMut<'_, Key>> {
+        self.keys.get(name).map(|key| key.borrow_mut())
+    }
+
+    pub fn contains(&self, name: &KeyName) -> bool {
+        self.keys.contains_key(name)
+    }
+
+    pub fn remove(&mut self, name: &KeyName) -> Option<SharedKey> {
+        self.keys.remove(name)
+    }
+
+    /// Removes the last key of the set.
+    pub fn pop(&mut self) -> Option<SharedKey> {
+        self.keys.pop_last().map(|(_, key)| key)
+    }
+
+    /// Gets the entry for `name`, to insert or update a key in place.
+    pub fn entry(&mut self, name: KeyName) -> Entry<'_> {
+        match self.keys.entry(name) {
+            btree_map::Entry::Occupied(entry) => Entry::Occupied(OccupiedEntry(entry)),
+            btree_map::Entry::Vacant(entry) => Entry::Vacant(VacantEntry(entry)),
+        }
+    }
+
+    pub fn values(&self) -> std::collections::btree_map::Iter<'_, KeyName, SharedKey> {
+        self.keys.iter()
+    }
+
+    /// Iterates over `parent` and all keys below it. For a cascading
+    /// `parent` this covers every namespace, like [`KeySet::below`].
+    pub fn range(&self, parent: &KeyName) -> Range<'_> {
+        self.subtrees(parent, Bound::Included)
+    }
+
+    /// Like [`KeySet::range`], but without `parent` itself.
+    pub fn descendants(&self, parent: &KeyName) -> Range<'_> {
+        self.subtrees(parent, Bound::Excluded)
+    }
+
+    fn subtrees(&self, parent: &KeyName, start: fn(KeyName) -> Bound<KeyName>) -> Range<'_> {
+        let ranges = subtree_parents(parent)
+            .map(|parent| {
+                let end = subtree_end(&parent);
+                self.keys.range((start(parent), end))
+            })
+            .collect();
+
+        Range { ranges }
+    }
+
+    /// Iterates over the keys directly below `parent`, skipping over the
+    /// keys further down.
+    pub fn children(&self, parent: &KeyName) -> Children<'_> {
+        let mut parents: VecDeque<KeyName> = subtree_parents(parent).collect();
+        let first = parents.pop_front().expect("a parent is in at least one namespace");
+
+        Children {
+            keys: &self.keys,
+            depth: parent.parts().len(),
+            parents,
+            end: subtree_end(&first),
+            start: Bound::Excluded(first),
+        }
+    }
+
+    /// Removes `point` and all keys below it, and returns them as a new
+    /// `KeySet`. A cascading `point` cuts the keys of every namespace.
+    pub fn cut(&mut self, point: &KeyName) -> KeySet {
+        let (cut, keep) = std::mem::take(&mut self.keys)
+            .into_iter()
+            .partition(|(name, _)| is_in_cut(name, point));
+
+        self.keys = keep;
+
+        KeySet { keys: cut }
+    }
+
+    /// Like [`KeySet::cut`], but leaves this set untouched. The returned
+    /// `KeySet` shares its keys with this one.
+    pub fn below(&self, point: &KeyName) -> KeySet {
+        let keys = self.keys
+            .iter()
+            .filter(|(name, _)| is_in_cut(name, point))
+            .map(|(name, key)| (name.clone(), key.clone()))
+            .collect();
+
+        KeySet { keys }
+    }
+}
+
+/// Keys that can be searched by name, so that they can be resolved like
+/// by libelektra's `ksLookup`.
+///
+/// Implemented by [`KeySet`], and by the key sets of the C API, which
+/// resolve lookups on their own keys.
+pub trait KeyLookup {
+    /// How a found key is returned, e.g. a [`SharedKey`] or a position.
+    type Found;
+
+    /// Looks up the key with exactly the name `name`.
+    fn find(&self, name: &KeyName) -> Option<Self::Found>;
+
+    /// Calls `f` with a key returned by [`KeyLookup::find`].
+    fn inspect<T>(&self, found: &Self::Found, f: impl FnOnce(&Key) -> T) -> T;
+
+    /// Resolves `key` the way libelektra's `ksLookup` does:
+    ///
+    /// - With `KDB_O_SPEC`, `key` is a spec key and is resolved like in
+    ///   [`KeySet::lookup_cascading`].
+    /// - A cascading `key` is resolved with [`KeySet::lookup_cascading`],
+    ///   unless `KDB_O_NOCASCADING` is given.
+    /// - Otherwise only the exact name is looked up.
+    ///
+    /// `KDB_O_POP` is left to the caller.
+    fn resolve(&self, key: &Key, options: KeyLookupFlags) -> Option<Self::Found> where Self: Sized {
+        let name = key.key_name();
+
+        if options.contains(KeyLookupFlags::KDB_O_SPEC) {
+            lookup_by_spec(self, key, options)
+        } else if name.namespace() == KeyNamespace::Cascading
+            && !options.contains(KeyLookupFlags::KDB_O_NOCASCADING) {
+            lookup_by_cascading(self, name, options)
+        } else {
+            self.find(name)
+        }
+    }
+}
+
+impl KeyLookup for KeySet {
+    type Found = SharedKey;
+
+    fn find(&self, name: &KeyName) -> Option<SharedKey> {
+        self.lookup(name)
+    }
+
+    fn inspect<T>(&self, found: &SharedKey, f: impl FnOnce(&Key) -> T) -> T {
+        f(&found.borrow())
+    }
+}
+
+fn lookup_by_cascading<L: KeyLookup>(keys: &L, name: &KeyName, options: KeyLookupFlags) -> Option<L::Found> {
+    if !options.contains(KeyLookupFlags::KDB_O_NOSPEC) {
+        let found = keys.find(&in_namespace(name, KeyNamespace::Spec))
+            .and_then(|spec| keys.inspect(&spec, |spec| lookup_by_spec(keys, spec, options)));
+
+        if found.is_some() {
+            return found;
+        }
+    }
+
+    lookup_in_namespaces(keys, name, &CASCADING_NAMESPACES)
+        .or_else(|| lookup_default(keys, name, options))
+}
+
+fn lookup_by_spec<L: KeyLookup>(keys: &L, spec: &Key, options: KeyLookupFlags) -> Option<L::Found> {
+    let mut namespaces: Vec<KeyNamespace> = meta_array(spec, "namespace")
+        .iter()
+        .filter_map(|namespace| KeyNamespace::from_str(namespace).ok())
+        .collect();
+
+    if namespaces.is_empty() {
+        namespaces = CASCADING_NAMESPACES.to_vec();
+    }
+
+    lookup_links(keys, spec, "override", options)
+        .or_else(|| lookup_in_namespaces(keys, spec.key_name(), &namespaces))
+        .or_else(|| lookup_links(keys, spec, "fallback", options))
+        .or_else(|| lookup_default(keys, spec.key_name(), options))
+}
+
+/// Looks up the keys named by the meta array `meta` of `spec`. Links are
+/// never resolved through other spec keys, to avoid cycles.
+fn lookup_links<L: KeyLookup>(keys: &L, spec: &Key, meta: &str, options: KeyLookupFlags) -> Option<L::Found> {
+    meta_array(spec, meta)
+        .iter()
+        .filter_map(|link| KeyName::from_str(link).ok())
+        .find_map(|link| {
+            if link.namespace() == KeyNamespace::Cascading {
+                lookup_by_cascading(keys, &link, options | KeyLookupFlags::KDB_O_NOSPEC)
+            } else {
+                keys.find(&link)
+            }
+        })
+}
+
+fn lookup_default<L: KeyLookup>(keys: &L, name: &KeyName, options: KeyLookupFlags) -> Option<L::Found> {
+    if options.contains(KeyLookupFlags::KDB_O_NODEFAULT) {
+        return None;
+    }
+
+    lookup_in_namespaces(keys, name, &[KeyNamespace::Cascading, KeyNamespace::Default])
+}
+
+fn lookup_in_namespaces<L: KeyLookup>(keys: &L, name: &KeyName, namespaces: &[KeyNamespace]) -> Option<L::Found> {
+    namespaces.iter()
+        .find_map(|&namespace| keys.find(&in_namespace(name, namespace)))
+}
+
+/// Collects the values of the meta array `name/#0`, `name/#1`, ... of `key`.
+fn meta_array(key: &Key, name: &str) -> Vec<String> {
+    (0..)
+        .map(|index| key.meta(format!("{}/{}", name, array_element(index))))
+        .take_while(Option::is_some)
+        .flatten()
+        .map(|meta| meta.string().unwrap_or_default().to_string())
+        .collect()
+}
+
+/// The canonical name of the array element `index`, e.g. `#_10`.
+fn array_element(index: usize) -> String {
+    let digits = index.to_string();
+
+    format!("#{}{}", "_".repeat(digits.len() - 1), digits)
+}
+
+fn in_namespace(name: &KeyName, namespace: KeyNamespace) -> KeyName {
+    KeyName::new(namespace, name.parts().to_vec())
+}
+
+fn subtree_end(parent: &KeyName) -> Bound<KeyName> {
+    parent.subtree_end().map_or(Bound::Unbounded, Bound::Excluded)
+}
+
+/// The parents whose subtrees make up the subtree of `parent`: `parent`
+/// itself, or `parent` in every namespace if it is cascading.
+fn subtree_parents(parent: &KeyName) -> impl Iterator<Item = KeyName> + '_ {
+    let cascading = parent.namespace() == KeyNamespace::Cascading;
+    let first = if cascading { KeyNamespace::None } else { parent.namespace() };
+
+    std::iter::successors(Some(first), move |namespace| if cascading { namespace.next() } else { None })
+        .map(move |namespace| in_namespace(parent, namespace))
+}
+
+/// Iterator over `parent` and the keys below it, see [`KeySet::range`].
+pub struct Range<'a> {
+    // one range per namespace, in the order of the namespaces
+    ranges: VecDeque<btree_map::Range<'a, KeyName, SharedKey>>,
+}
+
+impl<'a> Iterator for Range<'a> {
+    type Item = (&'a KeyName, &'a SharedKey);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.ranges.front_mut()?.next() {
+                Some(entry) => return Some(entry),
+                None => self.ranges.pop_front(),
+            };
+        }
+    }
+}
+
+impl<'a> DoubleEndedIterator for Range<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.ranges.back_mut()?.next_back() {
+                Some(entry) => return Some(entry),
+                None => self.ranges.pop_back(),
+            };
+        }
+    }
+}
+
+/// Iterator over the direct children of a key, see [`KeySet::children`].
+pub struct Children<'a> {
+    keys: &'a BTreeMap<KeyName, SharedKey>,
+    depth: usize,
+    // the parents in the namespaces that are still to be visited
+    parents: VecDeque<KeyName>,
+    start: Bound<KeyName>,
+    end: Bound<KeyName>,
+}
+
+impl<'a> Iterator for Children<'a> {
+    type Item = (&'a KeyName, &'a SharedKey);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let next = self.keys
+                .range((self.start.clone(), self.end.clone()))
+                .next();
+
+            let (name, key) = match next {
+                Some(x) => x,
+                None => {
+                    let parent = self.parents.pop_front()?;
+                    self.end = subtree_end(&parent);
+                    self.start = Bound::Excluded(parent);
+                    continue;
+                }
+            };
+
+            // Continue after the subtree of this child, whether the child
+            // itself is in the set or only keys further below it.
+            let child = KeyName::new(name.namespace(), name.parts()[..=self.depth].to_vec());
+            self.start = subtree_end(&child);
+
+            if name.parts().len() == self.depth + 1 {
+                return Some((name, key));
+            }
+        }
+    }
+}
+
+fn is_in_cut(name: &KeyName, point: &KeyName) -> bool {
+    let namespace_matches = point.namespace() == KeyNamespace::Cascading
+        || name.namespace() == point.namespace();
+
+    namespace_matches && name.parts().starts_with(point.parts())
+}
+
+/// A view into a single key of a `KeySet`, see [`KeySet::entry`].
+pub enum Entry<'a> {
+    Occupied(OccupiedEntry<'a>),
+    Vacant(VacantEntry<'a>),
+}
+
+pub struct OccupiedEntry<'a>(btree_map::OccupiedEntry<'a, KeyName, SharedKey>);
+
+pub struct VacantEntry<'a>(btree_map::VacantEntry<'a, KeyName, SharedKey>);
+
+impl<'a> Entry<'a> {
+    pub fn name(&self) -> &KeyName {
+        match self {
+            Entry::Occupied(entry) => entry.name(),
+            Entry::Vacant(entry) => entry.name(),
+        }
+    }
+
+    /// Returns the existing key, or inserts a new key without a value.
+    pub fn or_insert(self) -> &'a SharedKey {
+        match self {
+            Entry::Occupied(entry) => entry.into_shared_key(),
+            Entry::Vacant(entry) => entry.insert(),
+        }
+    }
+
+    /// Calls `f` on the key if it already exists.
+    pub fn and_modify<F: FnOnce(&mut Key)>(self, f: F) -> Self {
+        if let Entry::Occupied(entry) = &self {
+            f(&mut entry.get_mut());
+        }
+
+        self
+    }
+}
+
+impl<'a> OccupiedEntry<'a> {
+    pub fn name(&self) -> &KeyName {
+        self.0.key()
+    }
+
+    pub fn get(&self) -> Ref<'_, Key> {
+        self.0.get().borrow()
+    }
+
+    pub fn get_mut(&self) -> RefMut<'_, Key> {
+        self.0.get().borrow_mut()
+    }
+
+    pub fn into_shared_key(self) -> &'a SharedKey {
+        self.0.into_mut()
+    }
+
+    pub fn remove(self) -> SharedKey {
+        self.0.remove()
+    }
+}
+
+impl<'a> VacantEntry<'a> {
+    pub fn name(&self) -> &KeyName {
+        self.0.key()
+    }
+
+    /// Inserts a new key without a value, with its name locked.
+    pub fn insert(self) -> &'a SharedKey {
+        let mut key = Key::new(self.0.key().clone());
+        key.lock(KeyLockFlags::KEY_LOCK_NAME);
+
+        self.0.insert(key.into_shared())
     }
 }
 
@@ -284,10 +1465,561 @@ mod tests {
     fn test_key_builder() {
         let key = KeyBuilder::from_str("user:/test/qwe/asd")
             .unwrap()
-            .value([1, 2, 3].to_vec())
+            .value(KeyValue::Binary([1, 2, 3].to_vec()))
             .build()
             .unwrap();
 
         assert_eq!(key.name(), "user:/test/qwe/asd");
+        assert_eq!(key.binary(), Some(&[1, 2, 3][..]));
+
+        let key = KeyBuilder::from_str("user:/a")
+            .unwrap()
+            .value(KeyValue::String(b"x".to_vec()))
+            .meta("type", "long")
+            .lock(KeyLockFlags::KEY_LOCK_VALUE)
+            .build()
+            .unwrap();
+
+        assert_eq!(key.string(), Some("x"));
+        assert_eq!(key.meta("type").unwrap().string(), Some("long"));
+        assert!(key.is_locked(KeyLockFlags::KEY_LOCK_VALUE));
+
+        let key = KeyBuilder::from_str("user:/a")
+            .unwrap()
+            .binary()
+            .value(KeyValue::String(b"x".to_vec()))
+            .build()
+            .unwrap();
+
+        assert_eq!(key.binary(), Some(&b"x"[..]));
+
+        let key = KeyBuilder::from_str("user:/a").unwrap().binary().build().unwrap();
+        assert!(key.is_binary());
+        assert_eq!(key.value(), None);
+
+        assert!(KeyBuilder::from_str("user:/a").unwrap().meta("user:/a", "x").build().is_err());
+    }
+
+    #[test]
+    fn test_key_name_canonicalization() {
+        let canonical = |name: &str| KeyName::from_str(name).unwrap().to_string();
+
+        assert_eq!(canonical("user://a///b/"), "user:/a/b");
+        assert_eq!(canonical("user:/a/./b/../c"), "user:/a/c");
+        assert_eq!(canonical("user:/a/.."), "user:/");
+        assert_eq!(canonical("user:/a/%/b"), "user:/a/%/b");
+        assert_eq!(canonical("user:/#10/#_10/#5"), "user:/#_10/#_10/#5");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_key_name_escaping() {
+        let key_name = KeyName::from_str(r"user:/http:\/\/example.com/\%/\../\#10/a\\b").unwrap();
+
+        assert_eq!(key_name.parts(), &[
+            b"http://example.com".to_vec(),
+            b"%".to_vec(),
+            b"..".to_vec(),
+            b"#10".to_vec(),
+            b"a\\b".to_vec(),
+        ]);
+        assert_eq!(key_name.to_string(), r"user:/http:\/\/example.com/\%/\../\#10/a\\b");
+
+        let mut key_name = KeyName::from_str("user:/a").unwrap();
+        key_name.append_part(b"");
+        key_name.append_part(b"#_1");
+        assert_eq!(key_name.to_string(), r"user:/a/%/\#_1");
+    }
+
+    #[test]
+    fn test_key_name_invalid() {
+        let error = |name: &str| match KeyName::from_str(name) {
+            Err(KeyError::InvalidNameError(reason)) => reason,
+            _ => panic!("{} should be invalid", name),
+        };
+
+        assert_eq!(error(""), KeyNameError::EmptyName);
+        assert_eq!(error("user"), KeyNameError::MissingLeadingSlash);
+        assert_eq!(error("user:"), KeyNameError::MissingLeadingSlash);
+        assert_eq!(error("user:a/b"), KeyNameError::MissingLeadingSlash);
+        assert_eq!(error("user:/a/../.."), KeyNameError::AboveRoot);
+        assert_eq!(error(r"user:/a\b"), KeyNameError::InvalidEscapeSequence);
+        assert_eq!(error(r"user:/\#abc"), KeyNameError::InvalidEscapeSequence);
+        assert_eq!(error(r"user:/a\"), KeyNameError::DanglingEscape);
+        assert_eq!(error("user:/#__1"), KeyNameError::InvalidArrayPart);
+        assert_eq!(error("user:/#01"), KeyNameError::InvalidArrayPart);
+    }
+
+    #[test]
+    fn test_key_append_name() {
+        let mut key = Key::from_str("user:/a/b").unwrap();
+
+        key.append_name(r"../c\/d").unwrap();
+        assert_eq!(key.name(), r"user:/a/c\/d");
+
+        assert!(key.append_name("../../..").is_err());
+        assert_eq!(key.name(), r"user:/a/c\/d");
+
+        key.append_base_name(b"e/f").unwrap();
+        assert_eq!(key.name(), r"user:/a/c\/d/e\/f");
+
+        let mut meta = Key::from_str("meta:/").unwrap();
+        assert!(matches!(
+            meta.append_base_name(b"user:"),
+            Err(KeyError::InvalidNameError(KeyNameError::NestedNamespace))
+        ));
+        assert_eq!(meta.name(), "meta:/");
+    }
+
+    #[test]
+    fn test_key_name_unescaped() {
+        let unescaped = |name: &str| KeyName::from_str(name).unwrap().unescaped();
+
+        assert_eq!(unescaped("user:/"), b"\x06\0\0");
+        assert_eq!(unescaped("/a"), b"\x01\0a\0");
+        assert_eq!(unescaped(r"user:/a\/b/%/#10"), b"\x06\0a/b\0\0#_10\0");
+    }
+
+    #[test]
+    fn test_key_ordering() {
+        let key = |name: &str| Key::from_str(name).unwrap();
+
+        assert_ne!(key("user:/a"), key("system:/a"));
+        assert!(key("/b") < key("user:/a"));
+        assert!(key("user:/a") < key("user:/a/b"));
+        assert!(key("user:/a/b") < key("user:/ab"));
+        assert!(key("user:/a/%") < key("user:/a/b"));
+        assert!(key("user:/z") < key("system:/a"));
+
+        let ks: KeySet = vec![key("system:/a"), key("user:/a/b"), key("user:/a"), key("/a")]
+            .into_iter()
+            .collect();
+
+        let names: Vec<&KeyName> = ks.values().map(|(name, _)| name).collect();
+        assert_eq!(names, [
+            key("/a").key_name(),
+            key("user:/a").key_name(),
+            key("user:/a/b").key_name(),
+            key("system:/a").key_name(),
+        ]);
+    }
+
+    #[test]
+    fn test_key_namespaces() {
+        for name in &["/a", "meta:/a", "spec:/a", "proc:/a", "dir:/a", "user:/a", "system:/a", "default:/a"] {
+            assert_eq!(KeyName::from_str(name).unwrap().to_string(), *name);
+        }
+
+        let mut key_name = KeyName::from_str("spec:/a/b").unwrap();
+        assert_eq!(key_name.namespace(), KeyNamespace::Spec);
+
+        key_name.set_namespace(KeyNamespace::System).unwrap();
+        assert_eq!(key_name.to_string(), "system:/a/b");
+
+        key_name.set_namespace(KeyNamespace::Cascading).unwrap();
+        assert_eq!(key_name.to_string(), "/a/b");
+
+        assert!(key_name.set_namespace(KeyNamespace::None).is_err());
+        assert_eq!(key_name.namespace(), KeyNamespace::Cascading);
+
+        assert!(KeyNamespace::from_str("usr").is_err());
+    }
+
+    #[test]
+    fn test_key_namespace_invalid() {
+        let error = |name: &str| match KeyName::from_str(name) {
+            Err(KeyError::InvalidNameError(reason)) => reason,
+            _ => panic!("{} should be invalid", name),
+        };
+
+        assert_eq!(error("usr:/a"), KeyNameError::UnknownNamespace);
+        assert_eq!(error("cascading:/a"), KeyNameError::UnknownNamespace);
+        assert_eq!(error("meta:/user:/a"), KeyNameError::NestedNamespace);
+
+        let mut key_name = KeyName::from_str("meta:/").unwrap();
+        assert!(key_name.append_name(b"system:/a").is_err());
+        assert!(key_name.append_name(b"type").is_ok());
+    }
+
+    #[test]
+    fn test_key_hierarchy() {
+        let key = |name: &str| Key::from_str(name).unwrap();
+
+        assert!(key("user:/a/b").is_below(&key("user:/a")));
+        assert!(key("user:/a/b/c").is_below(&key("user:/a")));
+        assert!(key("user:/a/b").is_below(&key("user:/")));
+        assert!(!key("user:/b").is_below(&key("user:/a")));
+        assert!(!key("user:/ab").is_below(&key("user:/a")));
+        assert!(!key("user:/a").is_below(&key("user:/a")));
+        assert!(!key(r"user:/a\/b").is_below(&key("user:/a")));
+
+        assert!(!key("system:/a/b").is_below(&key("user:/a")));
+        assert!(key("system:/a/b").is_below(&key("/a")));
+        assert!(key("/a/b").is_below(&key("user:/a")));
+
+        assert!(key("user:/a").is_below_or_same(&key("user:/a")));
+        assert!(key("user:/a").is_below_or_same(&key("/a")));
+        assert!(!key("user:/a").is_below_or_same(&key("user:/a/b")));
+
+        assert!(key("user:/a/b").is_directly_below(&key("user:/a")));
+        assert!(!key("user:/a/b/c").is_directly_below(&key("user:/a")));
+        assert!(!key("user:/a").is_directly_below(&key("user:/a")));
+    }
+
+    #[test]
+    fn test_key_meta() {
+        let mut key = Key::from_str("user:/a").unwrap();
+
+        key.set_meta("type", "long").unwrap();
+        key.set_meta("meta:/check/range", "0-10").unwrap();
+
+        assert_eq!(key.meta("meta:/type").unwrap().string(), Some("long"));
+        assert_eq!(key.meta("check/range").unwrap().name(), "meta:/check/range");
+        assert_eq!(key.meta_keys().size(), 2);
+
+        assert!(key.set_meta("user:/type", "long").is_err());
+        assert!(key.set_meta("", "long").is_err());
+
+        key.set_meta(b"caf\xe9", b"\xff").unwrap();
+        assert_eq!(key.meta(b"meta:/caf\xe9").unwrap().value(), Some(&KeyValue::String(b"\xff".to_vec())));
+
+        assert!(key.remove_meta("type").unwrap().is_some());
+        assert!(key.meta("type").is_none());
+    }
+
+    #[test]
+    fn test_key_copy_meta() {
+        let mut source = Key::from_str("user:/source").unwrap();
+        source.set_meta("type", "long").unwrap();
+        source.set_meta("default", "5").unwrap();
+
+        let mut dest = Key::from_str("user:/dest").unwrap();
+        dest.set_meta("description", "port").unwrap();
+
+        dest.copy_meta_from(&source, "type").unwrap();
+        assert_eq!(dest.meta("type").unwrap().string(), Some("long"));
+
+        dest.copy_meta_from(&source, "description").unwrap();
+        assert!(dest.meta("description").is_none());
+
+        dest.set_meta("description", "port").unwrap();
+        dest.copy_all_meta_from(&source).unwrap();
+        assert_eq!(dest.meta_keys().size(), 3);
+    }
+
+    #[test]
+    fn test_key_string_and_binary() {
+        let mut key = Key::from_str("user:/a").unwrap();
+        assert_eq!(key.string(), Some(""));
+        assert!(key.is_string());
+        assert_eq!(key.binary(), None);
+
+        key.set_binary(b"\0\x01").unwrap();
+        assert!(key.is_binary());
+        assert_eq!(key.meta("binary").unwrap().string(), Some(""));
+        assert_eq!(key.binary(), Some(&b"\0\x01"[..]));
+        assert_eq!(key.string(), None);
+
+        key.set_string("value").unwrap();
+        assert!(key.meta("binary").is_none());
+        assert_eq!(key.string(), Some("value"));
+        assert_eq!(key.value(), Some(&KeyValue::String(b"value".to_vec())));
+
+        key.set_meta("binary", "").unwrap();
+        assert!(key.is_binary());
+    }
+
+    #[test]
+    fn test_key_lock() {
+        let mut key = Key::from_str("user:/a").unwrap();
+
+        key.lock(KeyLockFlags::KEY_LOCK_VALUE);
+        assert!(key.is_locked(KeyLockFlags::KEY_LOCK_VALUE));
+        assert!(!key.is_locked(KeyLockFlags::KEY_LOCK_NAME | KeyLockFlags::KEY_LOCK_META));
+
+        assert!(key.set_string("value").is_err());
+        assert!(key.set_meta("type", "string").is_ok());
+        assert!(key.append_name("b").is_ok());
+
+        key.lock(KeyLockFlags::KEY_LOCK_META);
+        assert!(key.set_meta("type", "long").is_err());
+        assert!(key.remove_meta("type").is_err());
+        assert_eq!(key.meta("type").unwrap().string(), Some("string"));
+
+        let mut ks = KeySet::default();
+        ks.append_key(key);
+
+        let mut key = ks.get_mut(&KeyName::from_str("user:/a/b").unwrap()).unwrap();
+        assert!(key.is_locked(KeyLockFlags::KEY_LOCK_NAME));
+        assert!(key.set_namespace(KeyNamespace::System).is_err());
+        assert!(key.append_base_name(b"c").is_err());
+        assert_eq!(key.name(), "user:/a/b");
+    }
+
+    #[test]
+    fn test_key_copy_from() {
+        let mut source = Key::from_str("user:/source").unwrap();
+        source.set_string("value").unwrap();
+        source.set_meta("type", "string").unwrap();
+
+        let mut dest = Key::from_str("system:/dest").unwrap();
+        dest.set_meta("description", "dest").unwrap();
+
+        dest.copy_from(&source, KeyCopyFlags::KEY_CP_NAME).unwrap();
+        assert_eq!(dest.name(), "user:/source");
+        assert_eq!(dest.string(), Some(""));
+
+        dest.copy_from(&source, KeyCopyFlags::KEY_CP_STRING).unwrap();
+        assert_eq!(dest.string(), Some("value"));
+        assert!(dest.meta("type").is_none());
+
+        dest.copy_from(&source, KeyCopyFlags::KEY_CP_META).unwrap();
+        assert!(dest.meta("type").is_some());
+        assert!(dest.meta("description").is_none());
+
+        source.set_binary(b"\x01").unwrap();
+        assert!(dest.copy_from(&source, KeyCopyFlags::KEY_CP_STRING).is_err());
+
+        dest.copy_from(&source, KeyCopyFlags::KEY_CP_VALUE).unwrap();
+        assert_eq!(dest.binary(), Some(&b"\x01"[..]));
+
+        dest.lock(KeyLockFlags::KEY_LOCK_META);
+        assert!(dest.copy_from(&Key::from_str("/other").unwrap(), KeyCopyFlags::KEY_CP_ALL).is_err());
+        assert_eq!(dest.name(), "user:/source");
+    }
+
+    #[test]
+    fn test_key_set_shared_keys() {
+        let key = Key::from_str("user:/a").unwrap().into_shared();
+
+        let mut first = KeySet::default();
+        let mut second = KeySet::default();
+        first.append_shared_key(key.clone());
+        second.append_shared_key(key.clone());
+        assert_eq!(Rc::strong_count(&key), 3);
+
+        key.borrow_mut().set_string("shared").unwrap();
+
+        let name = KeyName::from_str("user:/a").unwrap();
+        assert_eq!(second.get(&name).unwrap().string(), Some("shared"));
+
+        drop(first);
+        assert_eq!(Rc::strong_count(&key), 2);
+
+        drop(second);
+        assert_eq!(Rc::strong_count(&key), 1);
+
+        // keys can be added while they are borrowed elsewhere
+        let unlocked = Key::from_str("user:/b").unwrap().into_shared();
+        let borrowed = unlocked.borrow();
+
+        let mut third = KeySet::default();
+        third.append_shared_key(unlocked.clone());
+        assert!(borrowed.is_locked(KeyLockFlags::KEY_LOCK_NAME));
+
+        third.append_shared_key(key.clone());
+        let in_third = third.get(&name).unwrap();
+
+        let mut fourth = KeySet::default();
+        fourth.append_shared_key(key.clone());
+        assert_eq!(in_third.string(), Some("shared"));
+    }
+
+    #[test]
+    fn test_key_set_access() {
+        let name = |name: &str| KeyName::from_str(name).unwrap();
+
+        let mut ks: KeySet = vec![
+            Key::from_str("user:/a").unwrap(),
+            Key::from_str("user:/b").unwrap(),
+        ].into_iter().collect();
+
+        assert!(ks.get(&name("user:/a")).is_some());
+        assert!(ks.get(&name("user:/a")).is_some());
+        assert!(ks.get(&name("user:/c")).is_none());
+
+        ks.get_mut(&name("user:/a")).unwrap().set_string("a").unwrap();
+        assert_eq!(ks.lookup(&name("user:/a")).unwrap().borrow().string(), Some("a"));
+
+        assert_eq!(ks.pop().unwrap().borrow().name(), "user:/b");
+        assert!(ks.remove(&name("user:/a")).is_some());
+        assert_eq!(ks.size(), 0);
+    }
+
+    #[test]
+    fn test_key_set_entry() {
+        let mut ks = KeySet::default();
+        let name = KeyName::from_str("user:/a").unwrap();
+
+        ks.entry(name.clone())
+            .or_insert()
+            .borrow_mut()
+            .set_string("first")
+            .unwrap();
+
+        ks.entry(name.clone())
+            .and_modify(|key| key.set_string("second").unwrap())
+            .or_insert();
+
+        assert_eq!(ks.size(), 1);
+        assert_eq!(ks.get(&name).unwrap().string(), Some("second"));
+        assert!(ks.get(&name).unwrap().is_locked(KeyLockFlags::KEY_LOCK_NAME));
+
+        match ks.entry(name.clone()) {
+            Entry::Occupied(entry) => assert_eq!(entry.remove().borrow().name(), "user:/a"),
+            Entry::Vacant(_) => panic!("user:/a should exist"),
+        }
+
+        assert!(!ks.contains(&name));
+    }
+
+    #[test]
+    fn test_key_set_cut() {
+        let name = |name: &str| KeyName::from_str(name).unwrap();
+
+        let mut ks: KeySet = vec![
+            "user:/app", "user:/app/a", "user:/app/a/b", "user:/application",
+            "user:/other", "system:/app/c", "/app/d",
+        ].into_iter().map(|name| Key::from_str(name).unwrap()).collect();
+
+        let below = ks.below(&name("user:/app"));
+        assert_eq!(below.size(), 3);
+        assert_eq!(ks.size(), 7);
+
+        let cut = ks.cut(&name("user:/app"));
+        let names: Vec<String> = cut.values().map(|(name, _)| name.to_string()).collect();
+        assert_eq!(names, vec!["user:/app", "user:/app/a", "user:/app/a/b"]);
+        assert_eq!(ks.size(), 4);
+        assert!(ks.contains(&name("user:/application")));
+
+        let cut = ks.cut(&name("/app"));
+        assert_eq!(cut.size(), 2);
+        assert!(cut.contains(&name("system:/app/c")));
+        assert!(cut.contains(&name("/app/d")));
+        assert_eq!(ks.size(), 2);
+
+        assert_eq!(ks.cut(&name("user:/missing")).size(), 0);
+        assert_eq!(ks.size(), 2);
+    }
+
+    #[test]
+    fn test_key_set_ranges() {
+        let name = |name: &str| KeyName::from_str(name).unwrap();
+        let names = |iter: &mut dyn Iterator<Item = (&KeyName, &SharedKey)>| {
+            iter.map(|(name, _)| name.to_string()).collect::<Vec<String>>()
+        };
+
+        let ks: KeySet = vec![
+            "user:/", "user:/a", "user:/a/b", "user:/a/b/c", "user:/a/d/e", "user:/a/f",
+            "user:/ab", "system:/a/b", "/a/g",
+        ].into_iter().map(|name| Key::from_str(name).unwrap()).collect();
+
+        assert_eq!(
+            names(&mut ks.range(&name("user:/a"))),
+            vec!["user:/a", "user:/a/b", "user:/a/b/c", "user:/a/d/e", "user:/a/f"]
+        );
+        assert_eq!(
+            names(&mut ks.descendants(&name("user:/a")).rev()),
+            vec!["user:/a/f", "user:/a/d/e", "user:/a/b/c", "user:/a/b"]
+        );
+        assert_eq!(names(&mut ks.children(&name("user:/a"))), vec!["user:/a/b", "user:/a/f"]);
+        assert_eq!(names(&mut ks.children(&name("user:/"))), vec!["user:/a", "user:/ab"]);
+        assert_eq!(names(&mut ks.range(&name("user:/"))).len(), 7);
+        assert_eq!(names(&mut ks.range(&name("user:/x"))).len(), 0);
+
+        // a cascading parent covers every namespace, like `below` does
+        assert_eq!(
+            names(&mut ks.children(&name("/a"))),
+            vec!["/a/g", "user:/a/b", "user:/a/f", "system:/a/b"]
+        );
+        assert_eq!(
+            names(&mut ks.descendants(&name("/a"))),
+            vec!["/a/g", "user:/a/b", "user:/a/b/c", "user:/a/d/e", "user:/a/f", "system:/a/b"]
+        );
+        assert_eq!(
+            names(&mut ks.range(&name("/a")).rev()),
+            vec!["system:/a/b", "user:/a/f", "user:/a/d/e", "user:/a/b/c", "user:/a/b", "user:/a", "/a/g"]
+        );
+        assert_eq!(
+            names(&mut ks.range(&name("/a"))),
+            names(&mut ks.below(&name("/a")).values())
+        );
+        assert_eq!(names(&mut ks.range(&name("/"))).len(), ks.size());
+    }
+
+    #[test]
+    fn test_key_set_lookup_with() {
+        let key = |name: &str| Key::from_str(name).unwrap();
+        let found = |ks: &mut KeySet, name: &str, options: KeyLookupFlags| {
+            ks.lookup_with(&key(name), options).map(|key| key.borrow().name())
+        };
+
+        let mut ks: KeySet = vec![
+            "user:/a", "system:/a", "default:/a", "dir:/b", "/c", "default:/c", "spec:/d", "system:/d",
+        ].into_iter().map(key).collect();
+
+        let none = KeyLookupFlags::empty();
+        assert_eq!(found(&mut ks, "/a", none).as_deref(), Some("user:/a"));
+        assert_eq!(found(&mut ks, "/b", none).as_deref(), Some("dir:/b"));
+        assert_eq!(found(&mut ks, "/c", none).as_deref(), Some("/c"));
+        assert_eq!(found(&mut ks, "/d", none).as_deref(), Some("system:/d"));
+        assert_eq!(found(&mut ks, "spec:/d", KeyLookupFlags::KDB_O_SPEC).as_deref(), Some("system:/d"));
+        assert_eq!(found(&mut ks, "system:/a", none).as_deref(), Some("system:/a"));
+        assert_eq!(found(&mut ks, "/a", KeyLookupFlags::KDB_O_NOCASCADING), None);
+        assert_eq!(found(&mut ks, "/c", KeyLookupFlags::KDB_O_NOCASCADING).as_deref(), Some("/c"));
+        assert_eq!(found(&mut ks, "/c", KeyLookupFlags::KDB_O_NODEFAULT), None);
+
+        assert_eq!(found(&mut ks, "/a", KeyLookupFlags::KDB_O_POP).as_deref(), Some("user:/a"));
+        assert_eq!(found(&mut ks, "/a", KeyLookupFlags::KDB_O_POP).as_deref(), Some("system:/a"));
+        assert_eq!(found(&mut ks, "/a", none).as_deref(), Some("default:/a"));
+        assert_eq!(ks.size(), 6);
+    }
+
+    #[test]
+    fn test_key_set_lookup_cascading() {
+        let key = |name: &str| Key::from_str(name).unwrap();
+        let name = |name: &str| KeyName::from_str(name).unwrap();
+        let found = |ks: &KeySet, cascading: &str| {
+            ks.lookup_cascading(&name(cascading))
+                .map(|(key, namespace)| (key.borrow().name(), namespace))
+        };
+
+        let mut spec = key("spec:/app/port");
+        spec.set_meta("override/#0", "/app/override").unwrap();
+        spec.set_meta("namespace/#0", "system").unwrap();
+        spec.set_meta("namespace/#1", "user").unwrap();
+        spec.set_meta("fallback/#0", "/app/fallback").unwrap();
+
+        let mut ks: KeySet = vec![
+            spec, key("user:/app/port"), key("system:/app/port"), key("dir:/app/port"),
+            key("user:/app/fallback"), key("default:/app/port"),
+            key("system:/other"), key("default:/other"),
+        ].into_iter().collect();
+
+        assert_eq!(found(&ks, "/app/port"), Some(("system:/app/port".to_string(), KeyNamespace::System)));
+        assert_eq!(found(&ks, "user:/app/port"), found(&ks, "/app/port"));
+
+        ks.append_key(key("proc:/app/override"));
+        assert_eq!(found(&ks, "/app/port"), Some(("proc:/app/override".to_string(), KeyNamespace::Proc)));
+        ks.remove(&name("proc:/app/override"));
+
+        ks.remove(&name("system:/app/port"));
+        ks.remove(&name("user:/app/port"));
+        assert_eq!(found(&ks, "/app/port"), Some(("user:/app/fallback".to_string(), KeyNamespace::User)));
+
+        ks.remove(&name("user:/app/fallback"));
+        assert_eq!(found(&ks, "/app/port"), Some(("default:/app/port".to_string(), KeyNamespace::Default)));
+
+        // the spec finds nothing, so the other namespaces are searched
+        ks.remove(&name("default:/app/port"));
+        assert_eq!(found(&ks, "/app/port"), Some(("dir:/app/port".to_string(), KeyNamespace::Dir)));
+
+        ks.remove(&name("dir:/app/port"));
+        assert_eq!(found(&ks, "/app/port"), None);
+
+        assert_eq!(found(&ks, "/other"), Some(("system:/other".to_string(), KeyNamespace::System)));
+        assert_eq!(found(&ks, "/missing"), None);
+
+        assert_eq!(array_element(7), "#7");
+        assert_eq!(array_element(1234), "#___1234");
+    }
+}
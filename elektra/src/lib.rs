@@ -1 +1,6 @@
-pub mod key;
\ No newline at end of file
+pub mod key;
+pub mod kdb;
+pub mod mount;
+pub mod backend;
+pub mod plugin;
+pub mod ecf;
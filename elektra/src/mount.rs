@@ -0,0 +1,407 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::backend::Backend;
+use crate::kdb::KdbError;
+use crate::key::{Key, KeyCopyFlags, KeyName, KeyNamespace, KeySet};
+use crate::plugin;
+
+/// The key below which the mountpoints are configured.
+pub const MOUNTPOINTS: &str = "system:/elektra/mountpoints";
+
+/// The storage plugin of mountpoints that do not name one.
+pub const DEFAULT_PLUGIN: &str = "ecf";
+
+/// The namespaces whose keys are stored by backends.
+pub const PERSISTENT_NAMESPACES: [KeyNamespace; 4] = [
+    KeyNamespace::Spec,
+    KeyNamespace::Dir,
+    KeyNamespace::User,
+    KeyNamespace::System,
+];
+
+/// The backends of a [`Kdb`](crate::kdb::Kdb) and where they are mounted.
+///
+/// The bootstrap backend is mounted at the root of every persistent
+/// namespace and at `system:/elektra`, so it stores the mountpoint
+/// configuration and all keys that are not below another mountpoint. The
+/// other mountpoints are configured in the bootstrap file, with the
+/// mountpoint name as base name below [`MOUNTPOINTS`] and the file in the
+/// key `path`:
+///
+/// ```text
+/// system:/elektra/mountpoints/user:\/app/path = app.ecf
+/// ```
+///
+/// Relative paths are resolved against the directory of the bootstrap
+/// file. A cascading mountpoint is mounted in every persistent namespace.
+/// The key `plugin` names the storage plugin, [`DEFAULT_PLUGIN`] if it is
+/// missing, and the keys below `config` are its configuration.
+#[derive(Debug)]
+pub struct Mountpoints {
+    backends: Vec<Backend>,
+    /// A trie of name parts per namespace, leading to the index of the
+    /// backend mounted there.
+    tries: BTreeMap<KeyNamespace, Node>,
+}
+
+#[derive(Debug, Default)]
+struct Node {
+    backend: Option<usize>,
+    children: BTreeMap<Vec<u8>, Node>,
+}
+
+impl Mountpoints {
+    /// Mounts only the bootstrap backend.
+    pub fn new(bootstrap: Backend) -> Mountpoints {
+        let mut tries = BTreeMap::new();
+
+        for namespace in PERSISTENT_NAMESPACES {
+            tries.insert(namespace, Node { backend: Some(0), children: BTreeMap::new() });
+        }
+
+        let elektra = KeyName::from_str("system:/elektra")
+            .expect("system:/elektra is a valid key name");
+        tries.get_mut(&KeyNamespace::System)
+            .expect("the system namespace is persistent")
+            .node_mut(elektra.parts())
+            .backend = Some(0);
+
+        Mountpoints {
+            backends: vec![bootstrap],
+            tries,
+        }
+    }
+
+    /// Reads the bootstrap file with the [`Ecf`](crate::ecf::Ecf) plugin and
+    /// mounts the mountpoints configured in it.
+    pub fn bootstrap(bootstrap: PathBuf) -> Result<Mountpoints, KdbError> {
+        let root = KeyName::new(KeyNamespace::Cascading, Vec::new());
+        let storage = plugin::open(DEFAULT_PLUGIN, KeySet::default(), &mut Key::new(root.clone()))?;
+
+        let mut mountpoints = Mountpoints::new(Backend::new(root, bootstrap, storage));
+
+        let config = mountpoints.backends[0].load()?;
+
+        let directory = mountpoints.backends[0].path()
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+
+        mountpoints.configure(&config, &directory)?;
+
+        Ok(mountpoints)
+    }
+
+    /// Mounts `backend` at its mountpoint.
+    pub fn mount(&mut self, backend: Backend) -> Result<(), KdbError> {
+        let mountpoint = backend.mountpoint().clone();
+
+        let namespaces = match mountpoint.namespace() {
+            KeyNamespace::Cascading => PERSISTENT_NAMESPACES.to_vec(),
+            namespace if PERSISTENT_NAMESPACES.contains(&namespace) => vec![namespace],
+            namespace => {
+                return Err(KdbError::Installation(format!("{}: keys in the {} namespace cannot be mounted", mountpoint, namespace)));
+            }
+        };
+
+        for namespace in &namespaces {
+            if self.tries[namespace].node(mountpoint.parts()).is_some_and(|node| node.backend.is_some()) {
+                return Err(KdbError::Installation(format!("{}: something is mounted there already", mountpoint)));
+            }
+        }
+
+        let index = self.backends.len();
+
+        for namespace in namespaces {
+            self.tries.get_mut(&namespace)
+                .expect("there is a trie for every persistent namespace")
+                .node_mut(mountpoint.parts())
+                .backend = Some(index);
+        }
+
+        self.backends.push(backend);
+
+        Ok(())
+    }
+
+    /// The backend that stores the key `name`, `None` for keys that are not
+    /// stored, like those in the `proc` namespace.
+    pub fn backend_for(&self, name: &KeyName) -> Option<&Backend> {
+        self.tries.get(&name.namespace())
+            .map(|trie| trie.lookup(name.parts()))
+            .map(|index| &self.backends[index])
+    }
+
+    pub fn backends(&self) -> &[Backend] {
+        &self.backends
+    }
+
+    pub(crate) fn backend(&self, index: usize) -> &Backend {
+        &self.backends[index]
+    }
+
+    pub(crate) fn backend_mut(&mut self, index: usize) -> &mut Backend {
+        &mut self.backends[index]
+    }
+
+    /// The indices of the backends storing keys below or at `parent`.
+    pub(crate) fn involved(&self, parent: &KeyName) -> Vec<usize> {
+        let mut involved = BTreeSet::new();
+
+        for (namespace, trie) in &self.tries {
+            if parent.namespace() != KeyNamespace::Cascading && parent.namespace() != *namespace {
+                continue;
+            }
+
+            involved.insert(trie.lookup(parent.parts()));
+
+            if let Some(node) = trie.node(parent.parts()) {
+                node.collect(&mut involved);
+            }
+        }
+
+        involved.into_iter().collect()
+    }
+
+    /// Splits `ks` into the keys of every backend, by cutting out the keys
+    /// below each mountpoint, deepest mountpoints first. Keys that are not
+    /// stored by any backend are left out.
+    pub(crate) fn split(&self, ks: &KeySet) -> BTreeMap<usize, KeySet> {
+        let mut mounts = Vec::new();
+
+        for (namespace, trie) in &self.tries {
+            trie.mounts(&KeyName::new(*namespace, Vec::new()), &mut mounts);
+        }
+
+        mounts.sort_by_key(|(mountpoint, _)| std::cmp::Reverse(mountpoint.parts().len()));
+
+        let mut rest = ks.clone();
+        let mut split: BTreeMap<usize, KeySet> = BTreeMap::new();
+
+        for (mountpoint, index) in mounts {
+            let part = split.entry(index).or_default();
+
+            for (_, key) in rest.cut(&mountpoint).values() {
+                part.append_shared_key(key.clone());
+            }
+        }
+
+        split
+    }
+
+    fn configure(&mut self, config: &KeySet, directory: &Path) -> Result<(), KdbError> {
+        let parent = KeyName::from_str(MOUNTPOINTS)
+            .expect("the mountpoint configuration is a valid key name");
+
+        // The mountpoints are the children of the configuration, whether
+        // they have a key of their own or only keys below them.
+        let mountpoints: BTreeSet<&[u8]> = config.descendants(&parent)
+            .map(|(name, _)| &name.parts()[parent.parts().len()][..])
+            .collect();
+
+        for part in mountpoints {
+            let mut name = parent.clone();
+            name.append_part(part);
+
+            let invalid = |reason: &str| KdbError::Installation(format!("{}: {}", name, reason));
+
+            let mountpoint = KeyName::from_bytes(part)
+                .map_err(|_| invalid("the mountpoint is not a valid key name"))?;
+
+            let setting = |setting: &str| {
+                let mut setting_name = name.clone();
+                setting_name.append_part(setting.as_bytes());
+                config.get(&setting_name)
+                    .and_then(|key| key.string().filter(|value| !value.is_empty()).map(str::to_string))
+            };
+
+            let path = setting("path").ok_or_else(|| invalid("the mountpoint has no path"))?;
+            let plugin = setting("plugin").unwrap_or_else(|| DEFAULT_PLUGIN.to_string());
+
+            let mut error_key = Key::new(name.clone());
+            let storage = plugin::open(&plugin, plugin_config(config, &name), &mut error_key)?;
+
+            self.mount(Backend::new(mountpoint, directory.join(path), storage))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The keys below `<mountpoint>/config` as plugin configuration, which
+/// like in libelektra has them in the `user` namespace.
+fn plugin_config(config: &KeySet, mountpoint: &KeyName) -> KeySet {
+    let mut parent = mountpoint.clone();
+    parent.append_part(b"config");
+
+    config.descendants(&parent)
+        .map(|(name, key)| {
+            let mut copy = Key::new(KeyName::new(KeyNamespace::User, name.parts()[parent.parts().len()..].to_vec()));
+            copy.copy_from(&key.borrow(), KeyCopyFlags::KEY_CP_VALUE | KeyCopyFlags::KEY_CP_META)
+                .expect("a new key is not locked");
+            copy
+        })
+        .collect()
+}
+
+impl Node {
+    fn node(&self, parts: &[Vec<u8>]) -> Option<&Node> {
+        parts.iter().try_fold(self, |node, part| node.children.get(part))
+    }
+
+    fn node_mut(&mut self, parts: &[Vec<u8>]) -> &mut Node {
+        parts.iter().fold(self, |node, part| node.children.entry(part.clone()).or_default())
+    }
+
+    /// The backend of the deepest mountpoint at or above `parts`.
+    fn lookup(&self, parts: &[Vec<u8>]) -> usize {
+        let mut node = self;
+        let mut backend = self.backend.expect("the root of a namespace is always mounted");
+
+        for part in parts {
+            node = match node.children.get(part) {
+                Some(x) => x,
+                None => break,
+            };
+
+            backend = node.backend.unwrap_or(backend);
+        }
+
+        backend
+    }
+
+    fn collect(&self, backends: &mut BTreeSet<usize>) {
+        backends.extend(self.backend);
+
+        for child in self.children.values() {
+            child.collect(backends);
+        }
+    }
+
+    fn mounts(&self, name: &KeyName, mounts: &mut Vec<(KeyName, usize)>) {
+        if let Some(backend) = self.backend {
+            mounts.push((name.clone(), backend));
+        }
+
+        for (part, child) in &self.children {
+            let mut name = name.clone();
+            name.append_part(part);
+            child.mounts(&name, mounts);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(name: &str) -> KeyName {
+        KeyName::from_str(name).unwrap()
+    }
+
+    fn backend(mountpoint: &str, path: &str) -> Backend {
+        let storage = plugin::open(DEFAULT_PLUGIN, KeySet::default(), &mut Key::from_str("/").unwrap()).unwrap();
+        Backend::new(name(mountpoint), PathBuf::from(path), storage)
+    }
+
+    fn mountpoints() -> Mountpoints {
+        let mut mountpoints = Mountpoints::new(backend("/", "bootstrap.ecf"));
+        mountpoints.mount(backend("/app", "app.ecf")).unwrap();
+        mountpoints.mount(backend("user:/app/hosts", "hosts.ecf")).unwrap();
+        mountpoints.mount(backend("system:/other", "other.ecf")).unwrap();
+        mountpoints
+    }
+
+    fn path_for(mountpoints: &Mountpoints, key: &str) -> Option<String> {
+        mountpoints.backend_for(&name(key)).map(|backend| backend.path().display().to_string())
+    }
+
+    #[test]
+    fn test_mountpoints_backend_for() {
+        let mountpoints = mountpoints();
+
+        assert_eq!(path_for(&mountpoints, "user:/app"), Some("app.ecf".into()));
+        assert_eq!(path_for(&mountpoints, "system:/app/x"), Some("app.ecf".into()));
+        assert_eq!(path_for(&mountpoints, "user:/app/hosts/a/b"), Some("hosts.ecf".into()));
+        assert_eq!(path_for(&mountpoints, "system:/app/hosts"), Some("app.ecf".into()));
+        assert_eq!(path_for(&mountpoints, "user:/other"), Some("bootstrap.ecf".into()));
+        assert_eq!(path_for(&mountpoints, "system:/elektra/mountpoints"), Some("bootstrap.ecf".into()));
+        assert_eq!(path_for(&mountpoints, "proc:/app"), None);
+
+        let mut mountpoints = mountpoints;
+        assert!(matches!(mountpoints.mount(backend("user:/app", "x")), Err(KdbError::Installation(_))));
+        assert!(matches!(mountpoints.mount(backend("/other", "x")), Err(KdbError::Installation(_))));
+        assert!(matches!(mountpoints.mount(backend("proc:/x", "x")), Err(KdbError::Installation(_))));
+        assert!(matches!(mountpoints.mount(backend("system:/elektra", "x")), Err(KdbError::Installation(_))));
+        assert_eq!(mountpoints.backends().len(), 4);
+    }
+
+    #[test]
+    fn test_mountpoints_involved() {
+        let mountpoints = mountpoints();
+
+        assert_eq!(mountpoints.involved(&name("user:/app")), vec![1, 2]);
+        assert_eq!(mountpoints.involved(&name("user:/app/hosts/x")), vec![2]);
+        assert_eq!(mountpoints.involved(&name("system:/app/x")), vec![1]);
+        assert_eq!(mountpoints.involved(&name("user:/")), vec![0, 1, 2]);
+        assert_eq!(mountpoints.involved(&name("/")), vec![0, 1, 2, 3]);
+        assert_eq!(mountpoints.involved(&name("proc:/")), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_mountpoints_split() {
+        let mountpoints = mountpoints();
+
+        let ks: KeySet = ["user:/app/a", "user:/app/hosts", "user:/app/hosts/x", "system:/app/hosts",
+            "system:/other/x", "system:/elektra/mountpoints", "user:/b", "proc:/app"]
+            .iter()
+            .map(|name| Key::from_str(name).unwrap())
+            .collect();
+
+        let split = mountpoints.split(&ks);
+        let names = |index| split[&index].values().map(|(name, _)| name.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(names(0), vec!["user:/b", "system:/elektra/mountpoints"]);
+        assert_eq!(names(1), vec!["user:/app/a", "system:/app/hosts"]);
+        assert_eq!(names(2), vec!["user:/app/hosts", "user:/app/hosts/x"]);
+        assert_eq!(names(3), vec!["system:/other/x"]);
+    }
+
+    #[test]
+    fn test_mountpoints_configure() {
+        let config: KeySet = [
+            ("user:\\/app/path", "app.ecf"),
+            ("user:\\/app/config/format", "compact"),
+            ("\\/hosts", ""),
+            ("\\/hosts/path", "/etc/hosts.ecf"),
+            ("\\/hosts/plugin", "ecf"),
+        ].iter().map(|(name, value)| {
+            let mut key = Key::from_str(&format!("{}/{}", MOUNTPOINTS, name)).unwrap();
+            key.set_string(value).unwrap();
+            key
+        }).collect();
+
+        let mut mountpoints = Mountpoints::new(backend("/", "/kdb/bootstrap.ecf"));
+        mountpoints.configure(&config, Path::new("/kdb")).unwrap();
+
+        assert_eq!(path_for(&mountpoints, "user:/app/x"), Some("/kdb/app.ecf".into()));
+        assert_eq!(path_for(&mountpoints, "dir:/hosts"), Some("/etc/hosts.ecf".into()));
+
+        let app = mountpoints.backend_for(&name("user:/app")).unwrap().storage();
+        assert_eq!(app.name(), "ecf");
+        assert_eq!(app.config().get(&name("user:/format")).unwrap().string(), Some("compact"));
+
+        let mut unknown = KeySet::default();
+        for (setting, value) in [("path", "x.ecf"), ("plugin", "unknown")] {
+            let mut key = Key::from_str(&format!("{}/\\/x/{}", MOUNTPOINTS, setting)).unwrap();
+            key.set_string(value).unwrap();
+            unknown.append_key(key);
+        }
+        assert!(matches!(mountpoints.configure(&unknown, Path::new("/kdb")), Err(KdbError::Installation(_))));
+
+        let missing: KeySet = vec![Key::from_str("system:/elektra/mountpoints/\\/x").unwrap()].into_iter().collect();
+        assert!(matches!(mountpoints.configure(&missing, Path::new("/kdb")), Err(KdbError::Installation(_))));
+    }
+}
@@ -0,0 +1,307 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Mutex, PoisonError};
+
+use crate::ecf::Ecf;
+use crate::kdb::KdbError;
+use crate::key::{Key, KeyName, KeyNamespace, KeySet};
+
+/// The key below which plugins describe themselves, see [`Plugin::contract`].
+pub const MODULES: &str = "system:/elektra/modules";
+
+/// A plugin, modeled on the plugin contract of libelektra.
+///
+/// Like [`Kdb::get`](crate::kdb::Kdb::get), `get`, `set`, `error` and
+/// `commit` return whether they changed anything. A backend calls them with
+/// the mountpoint as parent key, whose value is the file to use. Only
+/// `name` and `get` have to be implemented.
+pub trait Plugin {
+    fn name(&self) -> &str;
+
+    /// Describes the plugin. By default this is just the key
+    /// `system:/elektra/modules/<name>`, plugins add keys like
+    /// `infos/provides` below it.
+    fn contract(&self) -> KeySet {
+        let mut contract = KeySet::default();
+        contract.append_key(Key::new(module_name(self.name())));
+        contract
+    }
+
+    /// Called once with the configuration of the plugin, before any other
+    /// function.
+    fn open(&mut self, _config: &KeySet, _error_key: &mut Key) -> Result<(), KdbError> {
+        Ok(())
+    }
+
+    fn get(&mut self, returned: &mut KeySet, parent: &mut Key) -> Result<bool, KdbError>;
+
+    fn set(&mut self, _returned: &mut KeySet, _parent: &mut Key) -> Result<bool, KdbError> {
+        Ok(false)
+    }
+
+    /// Called instead of `commit` if storing the keys failed after `set`.
+    fn error(&mut self, _returned: &mut KeySet, _parent: &mut Key) -> Result<bool, KdbError> {
+        Ok(false)
+    }
+
+    /// Called once the keys passed to `set` are stored.
+    fn commit(&mut self, _returned: &mut KeySet, _parent: &mut Key) -> Result<bool, KdbError> {
+        Ok(false)
+    }
+
+    fn close(&mut self, _error_key: &mut Key) -> Result<(), KdbError> {
+        Ok(())
+    }
+}
+
+/// The name of the contract of the plugin `name`.
+pub fn module_name(name: &str) -> KeyName {
+    let mut module: KeyName = MODULES.parse().expect("the modules key is a valid key name");
+    module.append_part(name.as_bytes());
+    module
+}
+
+/// An opened plugin together with its configuration.
+///
+/// Errors are reported on the key passed along, like [`Kdb`](crate::kdb::Kdb)
+/// does. A `get` for the contract of the plugin is answered with
+/// [`Plugin::contract`]. The plugin is closed when this is dropped, unless
+/// [`PluginInstance::close`] was called already.
+pub struct PluginInstance {
+    plugin: Box<dyn Plugin>,
+    config: KeySet,
+    closed: bool,
+}
+
+impl PluginInstance {
+    pub fn open(mut plugin: Box<dyn Plugin>, config: KeySet, error_key: &mut Key) -> Result<PluginInstance, KdbError> {
+        report(plugin.open(&config, error_key), error_key)?;
+
+        Ok(PluginInstance {
+            plugin,
+            config,
+            closed: false,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        self.plugin.name()
+    }
+
+    pub fn config(&self) -> &KeySet {
+        &self.config
+    }
+
+    pub fn contract(&self) -> KeySet {
+        self.plugin.contract()
+    }
+
+    pub fn get(&mut self, returned: &mut KeySet, parent: &mut Key) -> Result<bool, KdbError> {
+        let module = module_name(self.name());
+
+        if parent.key_name().is_below_or_same(&module) {
+            returned.cut(&module);
+
+            for (_, key) in self.plugin.contract().values() {
+                returned.append_shared_key(key.clone());
+            }
+
+            return Ok(true);
+        }
+
+        report(self.plugin.get(returned, parent), parent)
+    }
+
+    pub fn set(&mut self, returned: &mut KeySet, parent: &mut Key) -> Result<bool, KdbError> {
+        report(self.plugin.set(returned, parent), parent)
+    }
+
+    pub fn error(&mut self, returned: &mut KeySet, parent: &mut Key) -> Result<bool, KdbError> {
+        report(self.plugin.error(returned, parent), parent)
+    }
+
+    pub fn commit(&mut self, returned: &mut KeySet, parent: &mut Key) -> Result<bool, KdbError> {
+        report(self.plugin.commit(returned, parent), parent)
+    }
+
+    pub fn close(mut self, error_key: &mut Key) -> Result<(), KdbError> {
+        self.closed = true;
+        report(self.plugin.close(error_key), error_key)
+    }
+}
+
+impl Drop for PluginInstance {
+    fn drop(&mut self) {
+        if !self.closed {
+            let _ = self.plugin.close(&mut Key::new(KeyName::new(KeyNamespace::Cascading, Vec::new())));
+        }
+    }
+}
+
+impl fmt::Debug for PluginInstance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PluginInstance")
+            .field("name", &self.name())
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+fn report<T>(result: Result<T, KdbError>, key: &mut Key) -> Result<T, KdbError> {
+    if let Err(error) = &result {
+        error.set_on(key);
+    }
+
+    result
+}
+
+/// Creates a new, not yet opened plugin.
+pub type PluginFactory = fn() -> Box<dyn Plugin>;
+
+/// The plugins that come with this crate.
+const BUILTIN: &[(&str, PluginFactory)] = &[
+    ("ecf", || Box::new(Ecf)),
+];
+
+static REGISTRY: Mutex<BTreeMap<String, PluginFactory>> = Mutex::new(BTreeMap::new());
+
+/// Registers a statically linked plugin, so that it can be opened by name,
+/// e.g. by mountpoints. Fails if there is a plugin with this name already.
+pub fn register(name: &str, factory: PluginFactory) -> Result<(), KdbError> {
+    let mut registry = REGISTRY.lock().unwrap_or_else(PoisonError::into_inner);
+
+    if BUILTIN.iter().any(|(builtin, _)| *builtin == name) || registry.contains_key(name) {
+        return Err(KdbError::Installation(format!("there is a plugin named {} already", name)));
+    }
+
+    registry.insert(name.to_string(), factory);
+
+    Ok(())
+}
+
+/// The names of the built-in and registered plugins.
+pub fn registered() -> Vec<String> {
+    let registry = REGISTRY.lock().unwrap_or_else(PoisonError::into_inner);
+
+    BUILTIN.iter()
+        .map(|(name, _)| name.to_string())
+        .chain(registry.keys().cloned())
+        .collect()
+}
+
+/// Loads plugins that are neither built-in nor registered, e.g. from shared
+/// libraries.
+pub type PluginLoader = fn(&str) -> Result<Box<dyn Plugin>, KdbError>;
+
+static LOADER: Mutex<Option<PluginLoader>> = Mutex::new(None);
+
+/// Sets the loader [`open`] falls back to.
+pub fn set_loader(loader: PluginLoader) {
+    *LOADER.lock().unwrap_or_else(PoisonError::into_inner) = Some(loader);
+}
+
+/// Opens the built-in or registered plugin `name` with `config`, or the one
+/// the loader set with [`set_loader`] finds.
+pub fn open(name: &str, config: KeySet, error_key: &mut Key) -> Result<PluginInstance, KdbError> {
+    let factory = BUILTIN.iter()
+        .find(|(builtin, _)| *builtin == name)
+        .map(|(_, factory)| *factory)
+        .or_else(|| REGISTRY.lock().unwrap_or_else(PoisonError::into_inner).get(name).copied());
+
+    let loader = *LOADER.lock().unwrap_or_else(PoisonError::into_inner);
+
+    let plugin = match (factory, loader) {
+        (Some(factory), _) => Ok(factory()),
+        (None, Some(loader)) => loader(name),
+        (None, None) => Err(KdbError::Installation(format!("there is no plugin named {}", name))),
+    };
+
+    match plugin {
+        Ok(plugin) => PluginInstance::open(plugin, config, error_key),
+        Err(error) => {
+            error.set_on(error_key);
+            Err(error)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    /// Rejects keys whose value is not a number, if configured to.
+    struct Numbers {
+        check: bool,
+    }
+
+    impl Plugin for Numbers {
+        fn name(&self) -> &str {
+            "numbers"
+        }
+
+        fn open(&mut self, config: &KeySet, _error_key: &mut Key) -> Result<(), KdbError> {
+            self.check = config.contains(&KeyName::from_str("user:/check").unwrap());
+            Ok(())
+        }
+
+        fn get(&mut self, _returned: &mut KeySet, _parent: &mut Key) -> Result<bool, KdbError> {
+            Ok(false)
+        }
+
+        fn set(&mut self, returned: &mut KeySet, _parent: &mut Key) -> Result<bool, KdbError> {
+            let invalid = returned.values()
+                .find(|(_, key)| self.check && key.borrow().string().is_some_and(|value| value.parse::<i64>().is_err()));
+
+            match invalid {
+                Some((name, _)) => Err(KdbError::ValidationSyntactic(format!("{} is not a number", name))),
+                None => Ok(false),
+            }
+        }
+    }
+
+    #[test]
+    fn test_plugin_instance() {
+        let mut config = KeySet::default();
+        config.append_key(Key::from_str("user:/check").unwrap());
+
+        let mut error_key = Key::from_str("/").unwrap();
+        let mut plugin = PluginInstance::open(Box::new(Numbers { check: false }), config, &mut error_key).unwrap();
+
+        let mut parent = Key::from_str("user:/app").unwrap();
+        let mut ks = KeySet::default();
+        let mut key = Key::from_str("user:/app/port").unwrap();
+        key.set_string("80").unwrap();
+        ks.append_key(key.clone());
+
+        assert_eq!(plugin.set(&mut ks, &mut parent), Ok(false));
+
+        key.set_string("http").unwrap();
+        ks.append_key(key);
+
+        assert!(matches!(plugin.set(&mut ks, &mut parent), Err(KdbError::ValidationSyntactic(_))));
+        assert_eq!(parent.meta("error/number").unwrap().string(), Some("C03100"));
+
+        let mut contract = KeySet::default();
+        let mut module = Key::new(module_name("numbers"));
+        assert_eq!(plugin.get(&mut contract, &mut module), Ok(true));
+        assert!(contract.contains(&KeyName::from_str("system:/elektra/modules/numbers").unwrap()));
+
+        plugin.close(&mut error_key).unwrap();
+    }
+
+    #[test]
+    fn test_plugin_registry() {
+        register("numbers", || Box::new(Numbers { check: false })).unwrap();
+        assert!(register("numbers", || Box::new(Numbers { check: false })).is_err());
+        assert!(register("ecf", || Box::new(Numbers { check: false })).is_err());
+
+        assert!(registered().contains(&"ecf".to_string()));
+        assert!(registered().contains(&"numbers".to_string()));
+
+        let mut error_key = Key::from_str("/").unwrap();
+        assert_eq!(open("numbers", KeySet::default(), &mut error_key).unwrap().name(), "numbers");
+        assert!(matches!(open("missing", KeySet::default(), &mut error_key), Err(KdbError::Installation(_))));
+        assert_eq!(error_key.meta("error/number").unwrap().string(), Some("C01200"));
+    }
+}